@@ -0,0 +1,71 @@
+//! Tracking the activity of the user, for ending idle sessions.
+
+use gloo_events::EventListener;
+use gloo_utils::document;
+use js_sys::Date;
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+/// Events considered user activity.
+const EVENTS: [&str; 5] = [
+    "pointerdown",
+    "pointermove",
+    "keydown",
+    "wheel",
+    "touchstart",
+];
+
+/// Keeps track of the last activity of the user.
+pub(crate) struct Activity {
+    /// The time of the last activity, in seconds since the epoch.
+    last: Rc<Cell<f64>>,
+    _listeners: Vec<EventListener>,
+}
+
+impl Activity {
+    /// Start tracking activity.
+    ///
+    /// The callback receives the time of the activity, but is only invoked once per interval.
+    pub fn new<F>(interval: Duration, callback: F) -> Self
+    where
+        F: Fn(f64) + 'static,
+    {
+        let now = Date::now() / 1000f64;
+        let last = Rc::new(Cell::new(now));
+        let reported = Rc::new(Cell::new(now));
+        let callback = Rc::new(callback);
+
+        let listeners = EVENTS
+            .into_iter()
+            .map(|event| {
+                let last = last.clone();
+                let reported = reported.clone();
+                let callback = callback.clone();
+                EventListener::new(&document(), event, move |_| {
+                    let now = Date::now() / 1000f64;
+                    last.set(now);
+                    if now - reported.get() >= interval.as_secs_f64() {
+                        reported.set(now);
+                        callback(now);
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            last,
+            _listeners: listeners,
+        }
+    }
+
+    /// A handle to the time of the last activity, in seconds since the epoch.
+    pub fn last(&self) -> Rc<Cell<f64>> {
+        self.last.clone()
+    }
+
+    /// Record activity which happened elsewhere, e.g. in another tab.
+    pub fn record(&self, at: f64) {
+        if at > self.last.get() {
+            self.last.set(at);
+        }
+    }
+}
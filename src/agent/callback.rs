@@ -0,0 +1,107 @@
+//! Receiving authorization responses from other browsing contexts (iframes, popups).
+
+use super::{ErrorKind, OAuth2Error};
+use gloo_events::EventListener;
+use gloo_timers::callback::{Interval, Timeout};
+use gloo_utils::window;
+use js_sys::Object;
+use num_traits::ToPrimitive;
+use reqwest::Url;
+use std::time::Duration;
+use tokio::sync::mpsc::channel;
+use wasm_bindgen::JsCast;
+use web_sys::{MessageEvent, Window};
+
+/// Name of the window used for popup based logins.
+const POPUP_TARGET: &str = "yew-oauth2-login";
+
+/// Window features used for popup based logins.
+const POPUP_FEATURES: &str = "popup,width=600,height=700";
+
+/// Open an empty popup window, to be navigated to the login page later on.
+///
+/// Browsers only allow opening a popup in response to a user interaction, so this must be
+/// called synchronously by the handler of that interaction.
+///
+/// Fails with [`ErrorKind::PopupBlocked`] if the browser refused to open the window.
+pub(crate) fn open_popup() -> Result<Window, OAuth2Error> {
+    window()
+        .open_with_url_and_target_and_features("", POPUP_TARGET, POPUP_FEATURES)
+        .ok()
+        .flatten()
+        .ok_or_else(|| OAuth2Error::new(ErrorKind::PopupBlocked))
+}
+
+/// Navigate a popup window to the URL.
+pub(crate) fn navigate(popup: &Window, url: &Url) -> Result<(), OAuth2Error> {
+    popup
+        .location()
+        .set_href(url.as_str())
+        .map_err(|_| OAuth2Error::start_login("Unable to navigate popup to login page"))
+}
+
+/// Interval for checking if the source window got closed.
+const CLOSED_CHECK_INTERVAL: u32 = 500;
+
+/// Wait for a callback page, loaded in `source`, to post its URL back to this window.
+///
+/// Only messages from the same origin, sent by the provided source window, are accepted. The
+/// callback page is expected to post its full URL (`location.href`) as a string.
+///
+/// If the source window gets closed before a response was received, this fails with
+/// [`ErrorKind::PopupClosed`].
+pub(crate) async fn receive(source: Window, timeout: Option<Duration>) -> Result<Url, OAuth2Error> {
+    let origin = window()
+        .location()
+        .origin()
+        .map_err(|_| OAuth2Error::internal("unable to get current origin".to_string()))?;
+
+    let (tx, mut rx) = channel(1);
+
+    let _listener = {
+        let tx = tx.clone();
+        let source = source.clone();
+        EventListener::new(&window(), "message", move |event| {
+            let Some(event) = event.dyn_ref::<MessageEvent>() else {
+                return;
+            };
+            if event.origin() != origin {
+                return;
+            }
+            if !event
+                .source()
+                .is_some_and(|s| Object::is(&s, source.as_ref()))
+            {
+                return;
+            }
+            if let Some(data) = event.data().as_string() {
+                let _ = tx.try_send(Ok(data));
+            }
+        })
+    };
+
+    let _closed = {
+        let tx = tx.clone();
+        Interval::new(CLOSED_CHECK_INTERVAL, move || {
+            if source.closed().unwrap_or_default() {
+                let _ = tx.try_send(Err(OAuth2Error::new(ErrorKind::PopupClosed)));
+            }
+        })
+    };
+
+    let _timeout = timeout.map(|timeout| {
+        let millis = timeout.as_millis().to_u32().unwrap_or(i32::MAX as u32);
+        Timeout::new(millis, move || {
+            let _ = tx.try_send(Err(OAuth2Error::login_result(
+                "Timeout waiting for the authorization response".to_string(),
+            )));
+        })
+    });
+
+    let data = rx.recv().await.ok_or_else(|| {
+        OAuth2Error::internal("callback channel closed unexpectedly".to_string())
+    })??;
+
+    Url::parse(&data)
+        .map_err(|err| OAuth2Error::login_result(format!("Invalid callback URL: {err}")))
+}
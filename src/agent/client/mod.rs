@@ -9,14 +9,20 @@ pub use self::oauth2::*;
 pub use openid::*;
 
 use crate::{
-    agent::{InnerConfig, LogoutOptions, OAuth2Error},
+    agent::{
+        DpopKey, ErrorKind, InnerConfig, LogoutOptions, OAuth2Error, RefreshOptions, tag_state,
+    },
+    config::PushedAuthorization,
     context::OAuth2Context,
 };
+use ::oauth2::{CsrfToken, RequestTokenError, Scope, basic::BasicErrorResponse};
 use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use js_sys::Date;
 use num_traits::ToPrimitive;
 use reqwest::Url;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::error::Error;
 use std::fmt::Debug;
 use std::time::Duration;
 
@@ -35,10 +41,13 @@ pub trait Client: 'static + Sized + Clone + Debug {
     type TokenResponse;
     type Configuration: Clone + Debug + PartialEq;
     type LoginState: Debug + Serialize + DeserializeOwned;
-    type SessionState: Clone + Debug;
+    type SessionState: Clone + Debug + Serialize + DeserializeOwned;
 
     async fn from_config(config: Self::Configuration) -> Result<Self, OAuth2Error>;
 
+    /// The client ID, as registered with the issuer.
+    fn client_id(&self) -> &str;
+
     fn set_redirect_uri(self, url: Url) -> Self;
 
     fn make_login_context(
@@ -57,8 +66,27 @@ pub trait Client: 'static + Sized + Clone + Debug {
         &self,
         refresh_token: String,
         session_state: Self::SessionState,
+        options: RefreshOptions,
     ) -> Result<(OAuth2Context, Self::SessionState), OAuth2Error>;
 
+    /// Get the URL to navigate to for an authorization request.
+    ///
+    /// If pushed authorization requests are used, the request is pushed to the issuer first,
+    /// using the same HTTP client as the token requests.
+    async fn authorization_url(&self, url: Url) -> Result<Url, OAuth2Error> {
+        Ok(url)
+    }
+
+    /// The key tokens are bound to, if DPoP is enabled.
+    fn dpop(&self) -> Option<&DpopKey> {
+        None
+    }
+
+    /// Get the ID token of the session, which can be used as `id_token_hint`.
+    fn id_token_hint(&self, _session_state: &Self::SessionState) -> Option<String> {
+        None
+    }
+
     /// Trigger the logout of the session
     ///
     /// Clients may choose to contact some back-channel or redirect to a logout URL.
@@ -76,3 +104,233 @@ fn expires(expires_in: Option<Duration>) -> Option<u64> {
         None
     }
 }
+
+/// Evaluate the endpoint for pushed authorization requests, if they should be used.
+///
+/// A configured endpoint takes precedence over a discovered one.
+fn pushed_authorization_endpoint(
+    mode: PushedAuthorization,
+    configured: Option<String>,
+    discovered: Option<Url>,
+) -> Result<Option<Url>, OAuth2Error> {
+    if mode == PushedAuthorization::Disabled {
+        return Ok(None);
+    }
+
+    let url = configured
+        .map(|url| Url::parse(&url))
+        .transpose()
+        .map_err(|err| {
+            OAuth2Error::configuration(format!("Unable to parse pushed_authorization_url: {err}"))
+        })?
+        .or(discovered);
+
+    match (mode, url) {
+        (PushedAuthorization::Required, None) => Err(OAuth2Error::configuration(
+            "Pushed authorization requests are required, but not supported by the issuer",
+        )),
+        (_, url) => Ok(url),
+    }
+}
+
+/// Create a random `state` parameter, tagged with the namespace of the agent.
+fn csrf_token(config: &InnerConfig) -> String {
+    tag_state(
+        CsrfToken::new_random().into_secret(),
+        config.namespace.as_deref(),
+    )
+}
+
+/// Convert the scopes reported by the issuer, if any.
+fn granted_scopes(scopes: Option<&Vec<Scope>>) -> Option<Vec<String>> {
+    scopes.map(|scopes| scopes.iter().map(|scope| scope.to_string()).collect())
+}
+
+/// Get the expiration timestamp of a refresh token, in seconds.
+///
+/// Refresh tokens are opaque to the client. However, some issuers (like Keycloak) use JWTs, in
+/// which case the `exp` claim is used. The token is not verified, as it is only used as a hint.
+fn refresh_expires(refresh_token: Option<&str>) -> Option<u64> {
+    #[derive(serde::Deserialize)]
+    struct Claims {
+        exp: u64,
+    }
+
+    let mut parts = refresh_token?.split('.');
+    let (Some(_), Some(payload), Some(_), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    serde_json::from_slice::<Claims>(&payload)
+        .ok()
+        .map(|claims| claims.exp)
+}
+
+/// The current time, as a timestamp in seconds.
+fn issued() -> Option<u64> {
+    (Date::now() / 1000f64).to_u64()
+}
+
+/// Convert the error of a token request.
+///
+/// Error responses of the issuer are converted based on their code, falling back to the provided
+/// kind. Failing to send the request is considered a network error, failing to parse the
+/// response (e.g. a gateway error page) is considered a temporary unavailability of the issuer.
+fn token_error<RE: Error + 'static>(
+    err: RequestTokenError<RE, BasicErrorResponse>,
+    kind: ErrorKind,
+    message: &str,
+) -> OAuth2Error {
+    let error = match &err {
+        RequestTokenError::ServerResponse(response) => {
+            let code = response.error().to_string();
+            let mut error = OAuth2Error::new(ErrorKind::from_code(&code).unwrap_or(kind))
+                .with_code(code)
+                .with_description(
+                    response
+                        .error_description()
+                        .cloned()
+                        .unwrap_or_else(|| message.to_string()),
+                );
+            if let Some(uri) = response.error_uri() {
+                error = error.with_uri(uri);
+            }
+            error
+        }
+        RequestTokenError::Request(_) => {
+            OAuth2Error::new(ErrorKind::Network).with_description(format!("{message}: {err}"))
+        }
+        RequestTokenError::Parse(..) | RequestTokenError::Other(_) => {
+            OAuth2Error::new(ErrorKind::Unavailable).with_description(format!("{message}: {err}"))
+        }
+    };
+
+    error.with_source(err)
+}
+
+/// Convert the error of a refresh request.
+fn refresh_error<RE: Error + 'static>(
+    err: RequestTokenError<RE, BasicErrorResponse>,
+) -> OAuth2Error {
+    token_error(err, ErrorKind::Refresh, "failed to exchange refresh token")
+}
+
+/// Convert the error of a code exchange.
+fn exchange_error<RE: Error + 'static>(
+    err: RequestTokenError<RE, BasicErrorResponse>,
+) -> OAuth2Error {
+    token_error(err, ErrorKind::LoginResult, "failed to exchange code")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jwt(payload: &str) -> String {
+        format!("e30.{}.c2ln", URL_SAFE_NO_PAD.encode(payload))
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn pushed_authorization_disabled() {
+        assert_eq!(
+            pushed_authorization_endpoint(
+                PushedAuthorization::Disabled,
+                Some("https://issuer/configured".into()),
+                Some(url("https://issuer/discovered")),
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn pushed_authorization_preferred() {
+        assert_eq!(
+            pushed_authorization_endpoint(
+                PushedAuthorization::Preferred,
+                None,
+                Some(url("https://issuer/discovered")),
+            )
+            .unwrap(),
+            Some(url("https://issuer/discovered"))
+        );
+        // the configured endpoint takes precedence
+        assert_eq!(
+            pushed_authorization_endpoint(
+                PushedAuthorization::Preferred,
+                Some("https://issuer/configured".into()),
+                Some(url("https://issuer/discovered")),
+            )
+            .unwrap(),
+            Some(url("https://issuer/configured"))
+        );
+    }
+
+    #[test]
+    fn pushed_authorization_preferred_fallback() {
+        // without an endpoint, the authorization request is sent as part of the URL
+        assert_eq!(
+            pushed_authorization_endpoint(PushedAuthorization::Preferred, None, None).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn pushed_authorization_required() {
+        assert_eq!(
+            pushed_authorization_endpoint(
+                PushedAuthorization::Required,
+                Some("https://issuer/configured".into()),
+                None,
+            )
+            .unwrap(),
+            Some(url("https://issuer/configured"))
+        );
+
+        let err =
+            pushed_authorization_endpoint(PushedAuthorization::Required, None, None).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Configuration);
+    }
+
+    #[test]
+    fn pushed_authorization_invalid_url() {
+        let err = pushed_authorization_endpoint(
+            PushedAuthorization::Preferred,
+            Some("not a url".into()),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Configuration);
+    }
+
+    #[test]
+    fn refresh_expires_from_jwt() {
+        assert_eq!(
+            refresh_expires(Some(&jwt(r#"{"exp":1700000000,"typ":"Refresh"}"#))),
+            Some(1700000000)
+        );
+    }
+
+    #[test]
+    fn refresh_expires_url_safe() {
+        // encodes to a payload containing URL-safe characters ('-' and '_')
+        let token = jwt(r#"{"exp":1700000000,"sub":"???>>>"}"#);
+        assert!(token.contains('_') || token.contains('-'));
+        assert_eq!(refresh_expires(Some(&token)), Some(1700000000));
+    }
+
+    #[test]
+    fn refresh_expires_opaque() {
+        assert_eq!(refresh_expires(None), None);
+        assert_eq!(refresh_expires(Some("opaque-refresh-token")), None);
+        assert_eq!(refresh_expires(Some("a.b.c.d")), None);
+        assert_eq!(refresh_expires(Some(&jwt(r#"{"sub":"user"}"#))), None);
+    }
+}
@@ -1,15 +1,24 @@
 use crate::{
     agent::{
-        InnerConfig, OAuth2Error,
-        client::{Client, LoginContext, expires},
+        AuthorizationDetailsFields, DpopClient, DpopKey, InnerConfig, OAuth2Error, RefreshOptions,
+        authorization_details_param,
+        client::{
+            Client, LoginContext, csrf_token, exchange_error, expires, granted_scopes, issued,
+            pushed_authorization_endpoint, refresh_error, refresh_expires,
+        },
+        par,
     },
-    config::oauth2,
+    config::{ResponseMode, oauth2},
     context::{Authentication, OAuth2Context},
 };
 use ::oauth2::{
     AuthUrl, AuthorizationCode, ClientId, CsrfToken, EndpointNotSet, EndpointSet,
-    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, Scope, TokenResponse, TokenUrl,
-    basic::{BasicClient, BasicTokenResponse},
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken, Scope, StandardRevocableToken,
+    StandardTokenResponse, TokenResponse, TokenUrl,
+    basic::{
+        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+        BasicTokenType,
+    },
     reqwest,
     url::Url,
 };
@@ -22,20 +31,45 @@ pub struct LoginState {
     pub pkce_verifier: String,
 }
 
+/// The token response, including the granted authorization details.
+type ExtendedTokenResponse = StandardTokenResponse<AuthorizationDetailsFields, BasicTokenType>;
+
+type ExtendedClient = ::oauth2::Client<
+    BasicErrorResponse,
+    ExtendedTokenResponse,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointSet,
+>;
+
 /// An OAuth2 based client implementation
 #[derive(Clone, Debug)]
 pub struct OAuth2Client {
     http_client: reqwest::Client,
-    client: BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>,
+    client: ExtendedClient,
+    response_mode: Option<ResponseMode>,
+    pushed_authorization_url: Option<Url>,
+    dpop: Option<DpopKey>,
 }
 
 impl OAuth2Client {
-    fn make_authenticated(result: BasicTokenResponse) -> OAuth2Context {
+    fn make_authenticated(&self, result: ExtendedTokenResponse) -> OAuth2Context {
         OAuth2Context::Authenticated(Authentication {
             access_token: result.access_token().secret().to_string(),
             id_token: None,
             refresh_token: result.refresh_token().map(|t| t.secret().to_string()),
             expires: expires(result.expires_in()),
+            scopes: granted_scopes(result.scopes()),
+            authorization_details: result.extra_fields().authorization_details.clone(),
+            refresh_expires: refresh_expires(result.refresh_token().map(|t| t.secret().as_str())),
+            issued: issued(),
+            session_start: None,
+            dpop: self.dpop.clone(),
             #[cfg(feature = "openid")]
             claims: None,
         })
@@ -44,7 +78,7 @@ impl OAuth2Client {
 
 #[async_trait(?Send)]
 impl Client for OAuth2Client {
-    type TokenResponse = BasicTokenResponse;
+    type TokenResponse = ExtendedTokenResponse;
     type Configuration = oauth2::Config;
     type LoginState = LoginState;
     type SessionState = ();
@@ -54,27 +88,57 @@ impl Client for OAuth2Client {
             client_id,
             auth_url,
             token_url,
+            response_mode,
+            pushed_authorization,
+            pushed_authorization_url,
+            dpop,
         } = config;
 
         let http_client = reqwest::ClientBuilder::new().build().map_err(|err| {
-            OAuth2Error::Configuration(format!("failed to create HTTP client: {err}"))
+            OAuth2Error::configuration(format!("failed to create HTTP client: {err}"))
         })?;
 
         let client =
-            BasicClient::new(ClientId::new(client_id))
+            ::oauth2::Client::new(ClientId::new(client_id))
                 .set_auth_uri(AuthUrl::new(auth_url).map_err(|err| {
-                    OAuth2Error::Configuration(format!("invalid auth URL: {err}"))
+                    OAuth2Error::configuration(format!("invalid auth URL: {err}"))
                 })?)
                 .set_token_uri(TokenUrl::new(token_url).map_err(|err| {
-                    OAuth2Error::Configuration(format!("invalid token URL: {err}"))
+                    OAuth2Error::configuration(format!("invalid token URL: {err}"))
                 })?);
 
+        let pushed_authorization_url =
+            pushed_authorization_endpoint(pushed_authorization, pushed_authorization_url, None)?;
+
         Ok(Self {
             http_client,
             client,
+            response_mode,
+            pushed_authorization_url,
+            dpop: match dpop {
+                true => Some(DpopKey::generate().await?),
+                false => None,
+            },
         })
     }
 
+    fn client_id(&self) -> &str {
+        self.client.client_id().as_str()
+    }
+
+    async fn authorization_url(&self, url: Url) -> Result<Url, OAuth2Error> {
+        par::authorization_url(
+            self.pushed_authorization_url.as_ref(),
+            &DpopClient::new(&self.http_client, self.dpop.as_ref()),
+            url,
+        )
+        .await
+    }
+
+    fn dpop(&self) -> Option<&DpopKey> {
+        self.dpop.as_ref()
+    }
+
     fn set_redirect_uri(mut self, url: Url) -> Self {
         self.client = self.client.set_redirect_uri(RedirectUrl::from_url(url));
         self
@@ -90,10 +154,16 @@ impl Client for OAuth2Client {
             .clone()
             .set_redirect_uri(RedirectUrl::from_url(redirect_url));
 
+        if config.max_age.is_some() || !config.acr_values.is_empty() {
+            return Err(OAuth2Error::start_login(
+                "Requesting a maximum authentication age or context class requires OpenID Connect",
+            ));
+        }
+
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
         let mut req = client
-            .authorize_url(CsrfToken::new_random)
+            .authorize_url(|| CsrfToken::new(csrf_token(config)))
             .add_scopes(
                 config
                     .scopes
@@ -107,6 +177,14 @@ impl Client for OAuth2Client {
             req = req.add_extra_param("audience".to_string(), audience.clone())
         }
 
+        if let Some(response_mode) = config.response_mode.or(self.response_mode) {
+            req = req.add_extra_param("response_mode", response_mode.as_str());
+        }
+
+        if let Some((name, value)) = authorization_details_param(&config.authorization_details)? {
+            req = req.add_extra_param(name, value);
+        }
+
         let (url, state) = req.url();
 
         Ok(LoginContext {
@@ -129,29 +207,39 @@ impl Client for OAuth2Client {
             .client
             .exchange_code(AuthorizationCode::new(code))
             .set_pkce_verifier(pkce_verifier)
-            .request_async(&self.http_client)
+            .request_async(&DpopClient::new(&self.http_client, self.dpop.as_ref()))
             .await
-            .map_err(|err| OAuth2Error::LoginResult(format!("failed to exchange code: {err}")))?;
+            .map_err(exchange_error)?;
 
         log::debug!("Exchange code result: {:?}", result);
 
-        Ok((Self::make_authenticated(result), ()))
+        Ok((self.make_authenticated(result), ()))
     }
 
     async fn exchange_refresh_token(
         &self,
         refresh_token: String,
         session_state: Self::SessionState,
+        options: RefreshOptions,
     ) -> Result<(OAuth2Context, Self::SessionState), OAuth2Error> {
-        let result = self
-            .client
-            .exchange_refresh_token(&RefreshToken::new(refresh_token))
-            .request_async(&self.http_client)
+        let refresh_token = RefreshToken::new(refresh_token);
+        let mut req = self.client.exchange_refresh_token(&refresh_token);
+
+        for scope in options.scopes {
+            req = req.add_scope(Scope::new(scope));
+        }
+        for (name, value) in options.params {
+            req = req.add_extra_param(name, value);
+        }
+        if let Some((name, value)) = authorization_details_param(&options.authorization_details)? {
+            req = req.add_extra_param(name, value);
+        }
+
+        let result = req
+            .request_async(&DpopClient::new(&self.http_client, self.dpop.as_ref()))
             .await
-            .map_err(|err| {
-                OAuth2Error::Refresh(format!("failed to exchange refresh token: {err}"))
-            })?;
+            .map_err(refresh_error)?;
 
-        Ok((Self::make_authenticated(result), session_state))
+        Ok((self.make_authenticated(result), session_state))
     }
 }
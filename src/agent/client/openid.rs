@@ -1,36 +1,57 @@
 use crate::{
     agent::{
-        InnerConfig, LogoutOptions, OAuth2Error,
-        client::{Client, LoginContext, expires},
+        AuthorizationDetailsFields, DpopClient, DpopKey, ErrorKind, InnerConfig, LogoutOptions,
+        OAuth2Error, RefreshOptions, authorization_details_param,
+        client::{
+            Client, LoginContext, csrf_token, exchange_error, expires, granted_scopes, issued,
+            pushed_authorization_endpoint, refresh_error, refresh_expires,
+        },
+        par,
     },
-    config::openid::{self, MetadataSource, MetadataUrls},
-    context::{Authentication, OAuth2Context},
+    config::{
+        ResponseMode,
+        openid::{self, MetadataSource, MetadataUrls},
+    },
+    context::{Authentication, Claims, OAuth2Context},
 };
 use async_trait::async_trait;
 use gloo_utils::window;
+use js_sys::Date;
 use oauth2::TokenResponse as _;
 use openidconnect::{
-    AuthUrl, AuthorizationCode, ClientId, CsrfToken, EmptyAdditionalClaims, EndpointNotSet,
-    EndpointSet, IdTokenClaims, IssuerUrl, JsonWebKeySet, JsonWebKeySetUrl, Nonce,
-    PkceCodeChallenge, PkceCodeVerifier, ProviderMetadata, RedirectUrl, RefreshToken, Scope,
-    TokenResponse, TokenUrl, UserInfoUrl,
+    AuthUrl, AuthenticationContextClass, AuthorizationCode, ClientId, CsrfToken,
+    EmptyAdditionalClaims, EndpointNotSet, EndpointSet, IdTokenClaims, IdTokenFields, IssuerUrl,
+    JsonWebKeySet, JsonWebKeySetUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier, ProviderMetadata,
+    RedirectUrl, RefreshToken, Scope, StandardErrorResponse, StandardTokenResponse, TokenResponse,
+    TokenUrl, UserInfoUrl,
     core::{
-        CoreAuthDisplay, CoreAuthenticationFlow, CoreClaimName, CoreClaimType, CoreClient,
-        CoreClientAuthMethod, CoreGenderClaim, CoreGrantType, CoreJsonWebKey,
-        CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm, CoreResponseMode,
-        CoreResponseType, CoreSubjectIdentifierType, CoreTokenResponse,
+        CoreAuthDisplay, CoreAuthPrompt, CoreAuthenticationFlow, CoreClaimName, CoreClaimType,
+        CoreClientAuthMethod, CoreErrorResponseType, CoreGenderClaim, CoreGrantType,
+        CoreJsonWebKey, CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm,
+        CoreJwsSigningAlgorithm, CoreResponseMode, CoreResponseType, CoreRevocableToken,
+        CoreRevocationErrorResponse, CoreSubjectIdentifierType, CoreTokenIntrospectionResponse,
+        CoreTokenType,
     },
 };
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, rc::Rc};
+use std::{fmt::Debug, rc::Rc, time::Duration};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OpenIdLoginState {
     pub pkce_verifier: String,
     pub nonce: String,
+    /// The requested maximum authentication age, in seconds.
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// The requested authentication context class references.
+    #[serde(default)]
+    pub acr_values: Vec<String>,
 }
 
+/// The tolerated clock skew, when verifying the `auth_time` claim.
+const AUTH_TIME_SKEW: Duration = Duration::from_secs(60);
+
 const DEFAULT_POST_LOGOUT_DIRECT_NAME: &str = "post_logout_redirect_uri";
 
 /// An OpenID Connect based client implementation
@@ -50,6 +71,12 @@ pub struct OpenIdClient {
     additional_trusted_audiences: Vec<String>,
     /// Specifies whether the issuer claim must match the expected issuer URL for the provider.
     pub require_issuer_match: bool,
+    /// The response mode to request, unless overridden by the login options.
+    response_mode: Option<ResponseMode>,
+    /// The endpoint for pushed authorization requests, if they should be used
+    pushed_authorization_url: Option<Url>,
+    /// The key for binding tokens, if DPoP is enabled
+    dpop: Option<DpopKey>,
 }
 
 /// Additional metadata read from the discovery endpoint
@@ -57,6 +84,8 @@ pub struct OpenIdClient {
 pub struct AdditionalProviderMetadata {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub end_session_endpoint: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pushed_authorization_request_endpoint: Option<Url>,
 }
 
 impl openidconnect::AdditionalProviderMetadata for AdditionalProviderMetadata {}
@@ -76,7 +105,30 @@ pub type ExtendedProviderMetadata = ProviderMetadata<
     CoreSubjectIdentifierType,
 >;
 
-pub type ExtendedClient = CoreClient<
+/// The token response, including the granted authorization details.
+pub type ExtendedTokenResponse = StandardTokenResponse<
+    IdTokenFields<
+        EmptyAdditionalClaims,
+        AuthorizationDetailsFields,
+        CoreGenderClaim,
+        CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
+    >,
+    CoreTokenType,
+>;
+
+pub type ExtendedClient = openidconnect::Client<
+    EmptyAdditionalClaims,
+    CoreAuthDisplay,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJsonWebKey,
+    CoreAuthPrompt,
+    StandardErrorResponse<CoreErrorResponseType>,
+    ExtendedTokenResponse,
+    CoreTokenIntrospectionResponse,
+    CoreRevocableToken,
+    CoreRevocationErrorResponse,
     EndpointSet,
     EndpointNotSet,
     EndpointNotSet,
@@ -85,9 +137,49 @@ pub type ExtendedClient = CoreClient<
     EndpointSet,
 >;
 
+impl OpenIdClient {
+    /// Verify the authentication against the requested maximum age and context classes.
+    ///
+    /// The current time is provided in seconds since the epoch.
+    fn verify_authentication(
+        claims: &Claims,
+        now: i64,
+        max_age: Option<u64>,
+        acr_values: &[String],
+    ) -> Result<(), OAuth2Error> {
+        if let Some(max_age) = max_age {
+            let auth_time = claims.auth_time().ok_or_else(|| {
+                OAuth2Error::new(ErrorKind::InsufficientAuthentication)
+                    .with_description("missing auth_time claim")
+            })?;
+            let age = now.saturating_sub(auth_time.timestamp());
+            let allowed =
+                i64::try_from(max_age.saturating_add(AUTH_TIME_SKEW.as_secs())).unwrap_or(i64::MAX);
+            if age > allowed {
+                return Err(OAuth2Error::new(ErrorKind::InsufficientAuthentication)
+                    .with_description(format!(
+                        "authentication is too old: {age}s, requested: {max_age}s"
+                    )));
+            }
+        }
+
+        if !acr_values.is_empty() {
+            let acr = claims.auth_context_ref().map(|acr| acr.as_str());
+            if !acr.is_some_and(|acr| acr_values.iter().any(|value| value == acr)) {
+                return Err(OAuth2Error::new(ErrorKind::InsufficientAuthentication)
+                    .with_description(format!(
+                        "authentication context class {acr:?} doesn't match the request"
+                    )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[async_trait(? Send)]
 impl Client for OpenIdClient {
-    type TokenResponse = CoreTokenResponse;
+    type TokenResponse = ExtendedTokenResponse;
     type Configuration = openid::Config;
     type LoginState = OpenIdLoginState;
     type SessionState = (
@@ -105,6 +197,10 @@ impl Client for OpenIdClient {
             post_logout_redirect_name,
             additional_trusted_audiences,
             require_issuer_match,
+            response_mode,
+            pushed_authorization,
+            pushed_authorization_url,
+            dpop,
         } = config;
 
         let http_client = openidconnect::reqwest::ClientBuilder::new()
@@ -112,13 +208,13 @@ impl Client for OpenIdClient {
             // .redirect(openidconnect::reqwest::redirect::Policy::none())
             .build()
             .map_err(|err| {
-                OAuth2Error::Configuration(format!("Failed to build HTTP client: {err}"))
+                OAuth2Error::configuration(format!("Failed to build HTTP client: {err}"))
             })?;
 
         let issuer = IssuerUrl::new(issuer_url)
-            .map_err(|err| OAuth2Error::Configuration(format!("invalid issuer URL: {err}")))?;
+            .map_err(|err| OAuth2Error::configuration(format!("invalid issuer URL: {err}")))?;
 
-        let (client, end_session_url) = match metadata_source {
+        let (client, end_session_url, discovered_pushed_authorization_url) = match metadata_source {
             MetadataSource::Discovery => {
                 Self::build_client_from_discovery(&http_client, issuer, client_id, end_session_url)
                     .await?
@@ -128,6 +224,13 @@ impl Client for OpenIdClient {
                     .await?
             }
         };
+
+        let pushed_authorization_url = pushed_authorization_endpoint(
+            pushed_authorization,
+            pushed_authorization_url,
+            discovered_pushed_authorization_url,
+        )?;
+
         Ok(Self {
             http_client,
             client,
@@ -136,9 +239,32 @@ impl Client for OpenIdClient {
             post_logout_redirect_name,
             additional_trusted_audiences,
             require_issuer_match,
+            response_mode,
+            pushed_authorization_url,
+            dpop: match dpop {
+                true => Some(DpopKey::generate().await?),
+                false => None,
+            },
         })
     }
 
+    fn client_id(&self) -> &str {
+        self.client.client_id().as_str()
+    }
+
+    async fn authorization_url(&self, url: Url) -> Result<Url, OAuth2Error> {
+        par::authorization_url(
+            self.pushed_authorization_url.as_ref(),
+            &DpopClient::new(&self.http_client, self.dpop.as_ref()),
+            url,
+        )
+        .await
+    }
+
+    fn dpop(&self) -> Option<&DpopKey> {
+        self.dpop.as_ref()
+    }
+
     fn set_redirect_uri(mut self, url: Url) -> Self {
         self.client = self.client.set_redirect_uri(RedirectUrl::from_url(url));
         self
@@ -156,9 +282,10 @@ impl Client for OpenIdClient {
 
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
+        let csrf_token = CsrfToken::new(csrf_token(config));
         let mut req = client.authorize_url(
             CoreAuthenticationFlow::AuthorizationCode,
-            CsrfToken::new_random,
+            move || csrf_token,
             Nonce::new_random,
         );
 
@@ -170,6 +297,21 @@ impl Client for OpenIdClient {
             req = req.add_extra_param("audience".to_string(), audience);
         }
 
+        if let Some(response_mode) = config.response_mode.or(self.response_mode) {
+            req = req.add_extra_param("response_mode", response_mode.as_str());
+        }
+
+        if let Some((name, value)) = authorization_details_param(&config.authorization_details)? {
+            req = req.add_extra_param(name, value);
+        }
+
+        if let Some(max_age) = config.max_age {
+            req = req.set_max_age(max_age).add_prompt(CoreAuthPrompt::Login);
+        }
+        for acr in &config.acr_values {
+            req = req.add_auth_context_value(AuthenticationContextClass::new(acr.clone()));
+        }
+
         let (url, state, nonce) = req.set_pkce_challenge(pkce_challenge).url();
 
         Ok(LoginContext {
@@ -178,6 +320,8 @@ impl Client for OpenIdClient {
             state: OpenIdLoginState {
                 pkce_verifier: pkce_verifier.secret().clone(),
                 nonce: nonce.secret().clone(),
+                max_age: config.max_age.map(|max_age| max_age.as_secs()),
+                acr_values: config.acr_values.clone(),
             },
         })
     }
@@ -193,16 +337,17 @@ impl Client for OpenIdClient {
             .client
             .exchange_code(AuthorizationCode::new(code))
             .set_pkce_verifier(pkce_verifier)
-            .request_async(&self.http_client)
+            .request_async(&DpopClient::new(&self.http_client, self.dpop.as_ref()))
             .await
-            .map_err(|err| OAuth2Error::LoginResult(format!("failed to exchange code: {err}")))?;
+            .map_err(exchange_error)?;
 
         log::debug!("Exchange code result: {:?}", result);
 
         let id_token = result.extra_fields().id_token().ok_or_else(|| {
-            OAuth2Error::LoginResult("Server did not return an ID token".to_string())
+            OAuth2Error::login_result("Server did not return an ID token".to_string())
         })?;
 
+        let additional_trusted_audiences = &self.additional_trusted_audiences;
         let claims = Rc::new(
             id_token
                 .clone()
@@ -212,21 +357,42 @@ impl Client for OpenIdClient {
                         .id_token_verifier()
                         .require_issuer_match(self.require_issuer_match)
                         .set_other_audience_verifier_fn(|aud| {
-                            self.additional_trusted_audiences.contains(aud)
+                            additional_trusted_audiences.contains(aud)
                         }),
                     &Nonce::new(state.nonce),
                 )
                 .map_err(|err| {
-                    OAuth2Error::LoginResult(format!("failed to verify ID token: {err}"))
+                    OAuth2Error::new(ErrorKind::InvalidToken)
+                        .with_description(format!("failed to verify ID token: {err}"))
+                        .with_source(err)
                 })?,
         );
 
+        Self::verify_authentication(
+            &claims,
+            (Date::now() / 1000f64) as i64,
+            state.max_age,
+            &state.acr_values,
+        )?;
+
         Ok((
             OAuth2Context::Authenticated(Authentication {
                 access_token: result.access_token().secret().to_string(),
                 id_token: result.id_token().map(|t| t.to_string()),
                 refresh_token: result.refresh_token().map(|t| t.secret().to_string()),
                 expires: expires(result.expires_in()),
+                scopes: granted_scopes(result.scopes()),
+                authorization_details: result
+                    .extra_fields()
+                    .extra_fields()
+                    .authorization_details
+                    .clone(),
+                refresh_expires: refresh_expires(
+                    result.refresh_token().map(|t| t.secret().as_str()),
+                ),
+                issued: issued(),
+                session_start: None,
+                dpop: self.dpop.clone(),
                 claims: Some(claims.clone()),
             }),
             (id_token.to_string(), claims),
@@ -237,15 +403,25 @@ impl Client for OpenIdClient {
         &self,
         refresh_token: String,
         session_state: Self::SessionState,
+        options: RefreshOptions,
     ) -> Result<(OAuth2Context, Self::SessionState), OAuth2Error> {
-        let result = self
-            .client
-            .exchange_refresh_token(&RefreshToken::new(refresh_token))
-            .request_async(&self.http_client)
+        let refresh_token = RefreshToken::new(refresh_token);
+        let mut req = self.client.exchange_refresh_token(&refresh_token);
+
+        for scope in options.scopes {
+            req = req.add_scope(Scope::new(scope));
+        }
+        for (name, value) in options.params {
+            req = req.add_extra_param(name, value);
+        }
+        if let Some((name, value)) = authorization_details_param(&options.authorization_details)? {
+            req = req.add_extra_param(name, value);
+        }
+
+        let result = req
+            .request_async(&DpopClient::new(&self.http_client, self.dpop.as_ref()))
             .await
-            .map_err(|err| {
-                OAuth2Error::Refresh(format!("failed to exchange refresh token: {err}"))
-            })?;
+            .map_err(refresh_error)?;
 
         Ok((
             OAuth2Context::Authenticated(Authentication {
@@ -253,12 +429,28 @@ impl Client for OpenIdClient {
                 id_token: result.id_token().map(|t| t.to_string()),
                 refresh_token: result.refresh_token().map(|t| t.secret().to_string()),
                 expires: expires(result.expires_in()),
+                scopes: granted_scopes(result.scopes()),
+                authorization_details: result
+                    .extra_fields()
+                    .extra_fields()
+                    .authorization_details
+                    .clone(),
+                refresh_expires: refresh_expires(
+                    result.refresh_token().map(|t| t.secret().as_str()),
+                ),
+                issued: issued(),
+                session_start: None,
+                dpop: self.dpop.clone(),
                 claims: Some(session_state.1.clone()),
             }),
             session_state,
         ))
     }
 
+    fn id_token_hint(&self, session_state: &Self::SessionState) -> Option<String> {
+        Some(session_state.0.clone())
+    }
+
     fn logout(&self, session_state: Self::SessionState, options: LogoutOptions) {
         if let Some(url) = &self.end_session_url {
             let mut url = url.clone();
@@ -315,11 +507,11 @@ impl OpenIdClient {
         issuer: IssuerUrl,
         client_id: String,
         end_session_url: Option<String>,
-    ) -> Result<(ExtendedClient, Option<Url>), OAuth2Error> {
+    ) -> Result<(ExtendedClient, Option<Url>, Option<Url>), OAuth2Error> {
         let metadata = ExtendedProviderMetadata::discover_async(issuer, http_client)
             .await
             .map_err(|err| {
-                OAuth2Error::Configuration(format!("Failed to discover client: {err}"))
+                OAuth2Error::configuration(format!("Failed to discover client: {err}"))
             })?;
 
         // Extract the URIs we MUST have
@@ -327,31 +519,35 @@ impl OpenIdClient {
 
         let token_uri = metadata
             .token_endpoint()
-            .ok_or_else(|| {
-                OAuth2Error::Configuration("Provider missing required token endpoint".into())
-            })?
+            .ok_or_else(|| OAuth2Error::configuration("Provider missing required token endpoint"))?
             .clone();
 
         let user_info_uri = metadata
             .userinfo_endpoint()
             .ok_or_else(|| {
-                OAuth2Error::Configuration("Provider missing required auth info endpoint".into())
+                OAuth2Error::configuration("Provider missing required auth info endpoint")
             })?
             .clone();
         let end_session_url = end_session_url
             .map(|url| Url::parse(&url))
             .transpose()
             .map_err(|err| {
-                OAuth2Error::Configuration(format!("Unable to parse end_session_url: {err}"))
+                OAuth2Error::configuration(format!("Unable to parse end_session_url: {err}"))
             })?
             .or_else(|| metadata.additional_metadata().end_session_endpoint.clone());
 
+        let pushed_authorization_url = metadata
+            .additional_metadata()
+            .pushed_authorization_request_endpoint
+            .clone();
+
         Ok((
-            CoreClient::from_provider_metadata(metadata, ClientId::new(client_id), None)
+            openidconnect::Client::from_provider_metadata(metadata, ClientId::new(client_id), None)
                 .set_auth_uri(auth_uri)
                 .set_token_uri(token_uri)
                 .set_user_info_url(user_info_uri),
             end_session_url,
+            pushed_authorization_url,
         ))
     }
 
@@ -361,37 +557,128 @@ impl OpenIdClient {
         client_id: String,
         end_session_url: Option<String>,
         urls: MetadataUrls,
-    ) -> Result<(ExtendedClient, Option<Url>), OAuth2Error> {
+    ) -> Result<(ExtendedClient, Option<Url>, Option<Url>), OAuth2Error> {
         let auth_uri = AuthUrl::new(urls.auth)
-            .map_err(|err| OAuth2Error::Configuration(format!("invalid auth URL: {err}")))?;
+            .map_err(|err| OAuth2Error::configuration(format!("invalid auth URL: {err}")))?;
 
         let token_uri = TokenUrl::new(urls.token)
-            .map_err(|err| OAuth2Error::Configuration(format!("invalid token URL: {err}")))?;
+            .map_err(|err| OAuth2Error::configuration(format!("invalid token URL: {err}")))?;
 
         let jwks_uri = JsonWebKeySetUrl::new(urls.jwks)
-            .map_err(|err| OAuth2Error::Configuration(format!("invalid jwks URL: {err}")))?;
+            .map_err(|err| OAuth2Error::configuration(format!("invalid jwks URL: {err}")))?;
 
         let jwks = JsonWebKeySet::fetch_async(&jwks_uri, http_client)
             .await
-            .map_err(|err| OAuth2Error::Configuration(format!("Could not fetch jwks: {err}")))?;
+            .map_err(|err| OAuth2Error::configuration(format!("Could not fetch jwks: {err}")))?;
 
         let user_info_uri = UserInfoUrl::new(urls.user_info).map_err(|err| {
-            OAuth2Error::Configuration(format!("Unable to parse user_info_url: {err}"))
+            OAuth2Error::configuration(format!("Unable to parse user_info_url: {err}"))
         })?;
 
         let end_session_url = end_session_url
             .map(|url| Url::parse(&url))
             .transpose()
             .map_err(|err| {
-                OAuth2Error::Configuration(format!("Unable to parse end_session_url: {err}"))
+                OAuth2Error::configuration(format!("Unable to parse end_session_url: {err}"))
             })?;
 
         Ok((
-            CoreClient::new(ClientId::new(client_id), issuer, jwks)
+            openidconnect::Client::new(ClientId::new(client_id), issuer, jwks)
                 .set_auth_uri(auth_uri)
                 .set_token_uri(token_uri)
                 .set_user_info_url(user_info_uri),
             end_session_url,
+            None,
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn claims(auth_time: Option<i64>, acr: Option<&str>) -> Claims {
+        let mut claims = json!({
+            "iss": "https://issuer",
+            "sub": "user",
+            "aud": "client",
+            "exp": NOW + 300,
+            "iat": NOW,
+        });
+        if let Some(auth_time) = auth_time {
+            claims["auth_time"] = auth_time.into();
+        }
+        if let Some(acr) = acr {
+            claims["acr"] = acr.into();
+        }
+        serde_json::from_value(claims).unwrap()
+    }
+
+    fn verify(
+        claims: &Claims,
+        max_age: Option<u64>,
+        acr_values: &[&str],
+    ) -> Result<(), OAuth2Error> {
+        let acr_values: Vec<_> = acr_values.iter().map(ToString::to_string).collect();
+        OpenIdClient::verify_authentication(claims, NOW, max_age, &acr_values)
+    }
+
+    fn insufficient(result: Result<(), OAuth2Error>) -> bool {
+        result.is_err_and(|err| err.kind == ErrorKind::InsufficientAuthentication)
+    }
+
+    #[test]
+    fn nothing_requested() {
+        assert!(verify(&claims(None, None), None, &[]).is_ok());
+    }
+
+    #[test]
+    fn max_age() {
+        let claims = claims(Some(NOW - 100), None);
+        assert!(verify(&claims, Some(300), &[]).is_ok());
+        // tolerating the clock skew
+        assert!(verify(&claims, Some(40), &[]).is_ok());
+        assert!(insufficient(verify(&claims, Some(39), &[])));
+        assert!(insufficient(verify(&claims, Some(0), &[])));
+    }
+
+    #[test]
+    fn max_age_out_of_range() {
+        let claims = claims(Some(NOW - 100), None);
+        assert!(verify(&claims, Some(u64::MAX), &[]).is_ok());
+        assert!(verify(&claims, Some(i64::MAX as u64), &[]).is_ok());
+    }
+
+    #[test]
+    fn max_age_without_auth_time() {
+        assert!(insufficient(verify(&claims(None, None), Some(300), &[])));
+    }
+
+    #[test]
+    fn acr() {
+        let claims = claims(None, Some("urn:mfa"));
+        assert!(verify(&claims, None, &["urn:mfa"]).is_ok());
+        assert!(verify(&claims, None, &["urn:pwd", "urn:mfa"]).is_ok());
+        assert!(insufficient(verify(&claims, None, &["urn:pwd"])));
+    }
+
+    #[test]
+    fn acr_missing() {
+        assert!(insufficient(verify(
+            &claims(None, None),
+            None,
+            &["urn:mfa"]
+        )));
+    }
+
+    #[test]
+    fn max_age_and_acr() {
+        let claims = claims(Some(NOW - 100), Some("urn:mfa"));
+        assert!(verify(&claims, Some(300), &["urn:mfa"]).is_ok());
+        assert!(insufficient(verify(&claims, Some(0), &["urn:mfa"])));
+        assert!(insufficient(verify(&claims, Some(300), &["urn:pwd"])));
+    }
+}
@@ -1,27 +1,201 @@
-use super::{LoginOptions, LogoutOptions};
+use super::{
+    LoginOptions, LogoutOptions, RefreshPolicy, RetryOptions, SilentLoginOptions, StorageBackend,
+    refresh::same_policy,
+};
 use crate::agent::Client;
-use std::time::Duration;
+use std::{rc::Rc, time::Duration};
 
 #[doc(hidden)]
 #[derive(Clone, Debug)]
 pub struct AgentConfiguration<C: Client> {
     pub config: C::Configuration,
+    pub namespace: Option<String>,
     pub scopes: Vec<String>,
     pub grace_period: Duration,
+    pub refresh_policy: Option<Rc<dyn RefreshPolicy>>,
     pub audience: Option<String>,
     pub max_expiration: Option<Duration>,
 
     pub default_login_options: Option<LoginOptions>,
     pub default_logout_options: Option<LogoutOptions>,
+    pub silent_login: Option<SilentLoginOptions>,
+    pub state_storage: StorageBackend,
+    pub token_storage: Option<StorageBackend>,
+    pub sync_tabs: bool,
+    pub refresh_retry: RetryOptions,
+    pub reconsent: bool,
+    pub idle_timeout: Option<Duration>,
+    pub session_limit: Option<Duration>,
+    pub session_warning: Option<Duration>,
 }
 
 impl<C: Client> PartialEq for AgentConfiguration<C> {
     fn eq(&self, other: &Self) -> bool {
         self.config == other.config
+            && self.namespace == other.namespace
             && self.scopes == other.scopes
             && self.grace_period == other.grace_period
+            && same_policy(&self.refresh_policy, &other.refresh_policy)
             && self.audience == other.audience
+            && self.max_expiration == other.max_expiration
+            && self.default_login_options == other.default_login_options
+            && self.default_logout_options == other.default_logout_options
+            && self.silent_login == other.silent_login
+            && self.state_storage == other.state_storage
+            && self.token_storage == other.token_storage
+            && self.sync_tabs == other.sync_tabs
+            && self.refresh_retry == other.refresh_retry
+            && self.reconsent == other.reconsent
+            && self.idle_timeout == other.idle_timeout
+            && self.session_limit == other.session_limit
+            && self.session_warning == other.session_warning
     }
 }
 
 impl<C: Client> Eq for AgentConfiguration<C> {}
+
+/// The impact of a configuration change on the current session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ConfigChange {
+    /// The issuer, client, audience, or namespace changed. The session is no longer valid.
+    Identity,
+    /// Additional scopes are requested, which the session might not have been granted.
+    ScopesGrown,
+    /// Only options changed, which can be applied to the current session.
+    Options,
+}
+
+impl ConfigChange {
+    pub fn evaluate<C: Client>(
+        previous: &AgentConfiguration<C>,
+        next: &AgentConfiguration<C>,
+    ) -> Self {
+        if previous.config != next.config
+            || previous.audience != next.audience
+            || previous.namespace != next.namespace
+        {
+            Self::Identity
+        } else if next
+            .scopes
+            .iter()
+            .any(|scope| !previous.scopes.contains(scope))
+        {
+            Self::ScopesGrown
+        } else {
+            Self::Options
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{agent::OAuth2Client, config::oauth2::Config};
+
+    fn config() -> AgentConfiguration<OAuth2Client> {
+        AgentConfiguration {
+            config: Config::new("client", "https://issuer/auth", "https://issuer/token"),
+            namespace: None,
+            scopes: vec!["read".into(), "write".into()],
+            grace_period: Duration::from_secs(30),
+            refresh_policy: None,
+            audience: None,
+            max_expiration: None,
+            default_login_options: None,
+            default_logout_options: None,
+            silent_login: None,
+            state_storage: StorageBackend::Memory,
+            token_storage: None,
+            sync_tabs: false,
+            refresh_retry: RetryOptions::default(),
+            reconsent: false,
+            idle_timeout: None,
+            session_limit: None,
+            session_warning: None,
+        }
+    }
+
+    #[test]
+    fn identity_changed() {
+        let previous = config();
+
+        let mut next = config();
+        next.config = Config::new("other", "https://issuer/auth", "https://issuer/token");
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Identity
+        );
+
+        let mut next = config();
+        next.audience = Some("api".into());
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Identity
+        );
+
+        let mut next = config();
+        next.namespace = Some("other".into());
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Identity
+        );
+    }
+
+    #[test]
+    fn identity_wins_over_scopes() {
+        let previous = config();
+        let mut next = config();
+        next.audience = Some("api".into());
+        next.scopes.push("admin".into());
+
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Identity
+        );
+    }
+
+    #[test]
+    fn scopes_grown() {
+        let previous = config();
+        let mut next = config();
+        next.scopes.push("admin".into());
+
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::ScopesGrown
+        );
+    }
+
+    #[test]
+    fn scopes_reduced_or_reordered() {
+        let previous = config();
+
+        let mut next = config();
+        next.scopes = vec!["read".into()];
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Options
+        );
+
+        let mut next = config();
+        next.scopes.reverse();
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Options
+        );
+    }
+
+    #[test]
+    fn options_only() {
+        let previous = config();
+        let mut next = config();
+        next.grace_period = Duration::from_secs(60);
+        next.sync_tabs = true;
+        next.idle_timeout = Some(Duration::from_secs(600));
+
+        assert_eq!(
+            ConfigChange::evaluate(&previous, &next),
+            ConfigChange::Options
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Deadlines of the session, shared with the components.
+
+use crate::context::Reason;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+struct Deadlines {
+    /// The time the token expires, capped by the maximum expiration.
+    expires: Option<f64>,
+    /// The time of the last activity, and the idle timeout.
+    idle: Option<(Rc<Cell<f64>>, Duration)>,
+    /// The end of the session limit.
+    limit: Option<f64>,
+}
+
+/// Deadlines of the session, in seconds since the epoch.
+///
+/// This is maintained by the agent, and read by components.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SessionDeadlines(Rc<RefCell<Deadlines>>);
+
+impl PartialEq for SessionDeadlines {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl SessionDeadlines {
+    pub fn set_expires(&self, expires: Option<f64>) {
+        self.0.borrow_mut().expires = expires;
+    }
+
+    pub fn set_limits(&self, idle: Option<(Rc<Cell<f64>>, Duration)>, limit: Option<f64>) {
+        let mut deadlines = self.0.borrow_mut();
+        deadlines.idle = idle;
+        deadlines.limit = limit;
+    }
+
+    /// The time the token expires.
+    pub fn expires(&self) -> Option<f64> {
+        self.0.borrow().expires
+    }
+
+    /// The time the session ends, due to the idle timeout or the session limit.
+    pub fn session_end(&self) -> Option<(f64, Reason)> {
+        let deadlines = self.0.borrow();
+
+        let idle = deadlines
+            .idle
+            .as_ref()
+            .map(|(last, timeout)| (last.get() + timeout.as_secs_f64(), Reason::IdleTimeout));
+        let limit = deadlines.limit.map(|limit| (limit, Reason::SessionLimit));
+
+        idle.into_iter()
+            .chain(limit)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+/// Convert an amount of seconds (e.g. the time until a deadline) into a duration.
+///
+/// Negative amounts result in zero, amounts exceeding the range of a duration in
+/// [`Duration::MAX`].
+pub(crate) fn duration_from_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs.max(0.0)).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn deadlines(idle: Option<(f64, u64)>, limit: Option<f64>) -> SessionDeadlines {
+        let deadlines = SessionDeadlines::default();
+        deadlines.set_limits(
+            idle.map(|(last, timeout)| (Rc::new(Cell::new(last)), Duration::from_secs(timeout))),
+            limit,
+        );
+        deadlines
+    }
+
+    #[test]
+    fn no_limits() {
+        assert_eq!(deadlines(None, None).session_end(), None);
+    }
+
+    #[test]
+    fn single_limit() {
+        assert_eq!(
+            deadlines(Some((1000.0, 300)), None).session_end(),
+            Some((1300.0, Reason::IdleTimeout))
+        );
+        assert_eq!(
+            deadlines(None, Some(2000.0)).session_end(),
+            Some((2000.0, Reason::SessionLimit))
+        );
+    }
+
+    #[test]
+    fn earliest_limit() {
+        assert_eq!(
+            deadlines(Some((1000.0, 300)), Some(2000.0)).session_end(),
+            Some((1300.0, Reason::IdleTimeout))
+        );
+        assert_eq!(
+            deadlines(Some((1000.0, 300)), Some(1200.0)).session_end(),
+            Some((1200.0, Reason::SessionLimit))
+        );
+    }
+
+    #[test]
+    fn activity_moves_idle_timeout() {
+        let last = Rc::new(Cell::new(1000.0));
+        let deadlines = SessionDeadlines::default();
+        deadlines.set_limits(Some((last.clone(), Duration::from_secs(300))), Some(1500.0));
+        assert_eq!(deadlines.session_end(), Some((1300.0, Reason::IdleTimeout)));
+
+        last.set(1400.0);
+        assert_eq!(
+            deadlines.session_end(),
+            Some((1500.0, Reason::SessionLimit))
+        );
+    }
+
+    #[test]
+    fn expires() {
+        let deadlines = SessionDeadlines::default();
+        assert_eq!(deadlines.expires(), None);
+        deadlines.set_expires(Some(1600.0));
+        assert_eq!(deadlines.expires(), Some(1600.0));
+        // the token expiration is not a limit of the session
+        assert_eq!(deadlines.session_end(), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration_from_secs(1.5), Duration::from_millis(1500));
+        assert_eq!(duration_from_secs(-10.0), Duration::ZERO);
+        assert_eq!(duration_from_secs(f64::MAX), Duration::MAX);
+        assert_eq!(duration_from_secs(f64::NAN), Duration::ZERO);
+    }
+}
@@ -0,0 +1,603 @@
+//! Sender-constrained tokens, using DPoP (RFC 9449).
+
+use super::OAuth2Error;
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use gloo_utils::window;
+use js_sys::{Array, JSON, Uint8Array};
+use oauth2::{AsyncHttpClient, CsrfToken, HttpRequest, HttpResponse, http};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{CryptoKey, CryptoKeyPair, EcKeyGenParams, EcdsaParams, SubtleCrypto};
+
+/// The header carrying the DPoP proof.
+pub const DPOP_HEADER: &str = "DPoP";
+
+/// The header carrying a nonce, provided by the server.
+pub const DPOP_NONCE_HEADER: &str = "DPoP-Nonce";
+
+/// The error code of a server, requiring a (new) nonce.
+const USE_DPOP_NONCE: &str = "use_dpop_nonce";
+
+/// A key pair for creating DPoP proofs.
+///
+/// The key pair is generated through WebCrypto, with a non-extractable private key. So it can be
+/// used for signing proofs, but can't be read by scripts. As it only lives as long as the page,
+/// tokens bound to it can't be used after a reload, or by other tabs. Therefore, sessions bound
+/// to a key are neither persisted nor synchronized between tabs.
+///
+/// Nonces provided by servers (using the `DPoP-Nonce` header) are kept per origin, and included
+/// in further proofs for that origin.
+#[derive(Clone)]
+pub struct DpopKey(Rc<InnerKey>);
+
+struct InnerKey {
+    signer: Box<dyn Signer>,
+    jwk: Jwk,
+    nonces: RefCell<HashMap<String, String>>,
+}
+
+/// The private key, signing the proofs.
+#[async_trait(?Send)]
+trait Signer {
+    /// Sign the input, returning the signature (ES256, in its raw `r || s` form).
+    async fn sign(&self, input: &[u8]) -> Result<Vec<u8>, OAuth2Error>;
+}
+
+/// A private key, held by WebCrypto.
+struct WebCryptoSigner(CryptoKey);
+
+#[async_trait(?Send)]
+impl Signer for WebCryptoSigner {
+    async fn sign(&self, input: &[u8]) -> Result<Vec<u8>, OAuth2Error> {
+        let signature = subtle()?
+            .sign_with_object_and_u8_array(
+                &EcdsaParams::new_with_str("ECDSA", "SHA-256"),
+                &self.0,
+                input,
+            )
+            .map_err(|err| crypto_error(err, "failed to sign proof"))?;
+        let signature = JsFuture::from(signature)
+            .await
+            .map_err(|err| crypto_error(err, "failed to sign proof"))?;
+        Ok(Uint8Array::new(&signature).to_vec())
+    }
+}
+
+impl Debug for DpopKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DpopKey")
+            .field("jwk", &self.0.jwk)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for DpopKey {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for DpopKey {}
+
+/// The public key, as JSON web key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Jwk {
+    kty: String,
+    crv: String,
+    x: String,
+    y: String,
+}
+
+/// The header of a proof.
+#[derive(Debug, Serialize)]
+struct ProofHeader<'a> {
+    typ: &'static str,
+    alg: &'static str,
+    jwk: &'a Jwk,
+}
+
+/// The claims of a proof.
+#[derive(Debug, Serialize)]
+pub(crate) struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ath: Option<String>,
+}
+
+impl ProofClaims {
+    /// Create the claims for a request, ignoring the query and fragment of the URL.
+    pub fn new(method: &str, url: &Url, nonce: Option<String>, ath: Option<String>) -> Self {
+        let mut htu = url.clone();
+        htu.set_query(None);
+        htu.set_fragment(None);
+
+        Self {
+            jti: CsrfToken::new_random().into_secret(),
+            htm: method.to_uppercase(),
+            htu: htu.to_string(),
+            iat: now(),
+            nonce,
+            ath,
+        }
+    }
+}
+
+impl DpopKey {
+    /// Generate a new key pair (ECDSA, using the P-256 curve).
+    pub async fn generate() -> Result<Self, OAuth2Error> {
+        let subtle = subtle()?;
+
+        let usages = Array::of2(&"sign".into(), &"verify".into());
+        let pair = subtle
+            .generate_key_with_object(&EcKeyGenParams::new("ECDSA", "P-256"), false, &usages)
+            .map_err(|err| crypto_error(err, "failed to generate key"))?;
+        let pair: CryptoKeyPair = JsFuture::from(pair)
+            .await
+            .map_err(|err| crypto_error(err, "failed to generate key"))?
+            .unchecked_into();
+
+        let jwk = subtle
+            .export_key("jwk", &pair.get_public_key())
+            .map_err(|err| crypto_error(err, "failed to export public key"))?;
+        let jwk = JsFuture::from(jwk)
+            .await
+            .and_then(|jwk| JSON::stringify(&jwk))
+            .map_err(|err| crypto_error(err, "failed to export public key"))?;
+        let jwk: Jwk = serde_json::from_str(&String::from(jwk))
+            .map_err(|err| OAuth2Error::internal(format!("invalid public key: {err}")))?;
+
+        Ok(Self::new(WebCryptoSigner(pair.get_private_key()), jwk))
+    }
+
+    /// A key signing with a dummy signature, for tests.
+    #[cfg(test)]
+    pub(crate) fn test() -> Self {
+        Self::new(
+            test::TestSigner,
+            Jwk {
+                kty: "EC".into(),
+                crv: "P-256".into(),
+                x: "x-coordinate".into(),
+                y: "y-coordinate".into(),
+            },
+        )
+    }
+
+    fn new(signer: impl Signer + 'static, jwk: Jwk) -> Self {
+        Self(Rc::new(InnerKey {
+            signer: Box::new(signer),
+            jwk,
+            nonces: Default::default(),
+        }))
+    }
+
+    /// Create a proof for a request, to be sent in the [`DPOP_HEADER`].
+    ///
+    /// When calling a resource server, the access token must be provided, binding the proof to
+    /// it. If the server of the URL provided a nonce before, it is included in the proof.
+    pub async fn proof(
+        &self,
+        method: &str,
+        url: &str,
+        access_token: Option<&str>,
+    ) -> Result<String, OAuth2Error> {
+        let url = Url::parse(url)
+            .map_err(|err| OAuth2Error::internal(format!("invalid URL for DPoP proof: {err}")))?;
+
+        let ath = access_token
+            .map(|access_token| URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes())));
+
+        let claims = ProofClaims::new(method, &url, self.nonce(&url), ath);
+        self.sign(&claims).await
+    }
+
+    /// Remember a nonce, provided by the server of the URL in the [`DPOP_NONCE_HEADER`].
+    ///
+    /// Returns `true` if the nonce changed.
+    pub fn set_nonce(&self, url: &str, nonce: impl Into<String>) -> bool {
+        let Ok(url) = Url::parse(url) else {
+            return false;
+        };
+        let nonce = nonce.into();
+        let previous = self
+            .0
+            .nonces
+            .borrow_mut()
+            .insert(url.origin().ascii_serialization(), nonce.clone());
+        previous.as_ref() != Some(&nonce)
+    }
+
+    fn nonce(&self, url: &Url) -> Option<String> {
+        self.0
+            .nonces
+            .borrow()
+            .get(&url.origin().ascii_serialization())
+            .cloned()
+    }
+
+    /// Sign the claims, creating the proof.
+    pub(crate) async fn sign(&self, claims: &ProofClaims) -> Result<String, OAuth2Error> {
+        let header = ProofHeader {
+            typ: "dpop+jwt",
+            alg: "ES256",
+            jwk: &self.0.jwk,
+        };
+        let input = format!("{}.{}", encode_json(&header)?, encode_json(claims)?);
+        let signature = self.0.signer.sign(input.as_bytes()).await?;
+
+        Ok(format!("{input}.{}", URL_SAFE_NO_PAD.encode(signature)))
+    }
+}
+
+/// The current time, in seconds since the epoch.
+#[cfg(target_arch = "wasm32")]
+fn now() -> u64 {
+    use num_traits::ToPrimitive;
+    (js_sys::Date::now() / 1000f64).to_u64().unwrap_or_default()
+}
+
+/// The current time, in seconds since the epoch.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+fn subtle() -> Result<SubtleCrypto, OAuth2Error> {
+    window()
+        .crypto()
+        .map(|crypto| crypto.subtle())
+        .map_err(|err| crypto_error(err, "WebCrypto is not available"))
+}
+
+fn encode_json<T: Serialize>(value: &T) -> Result<String, OAuth2Error> {
+    serde_json::to_vec(value)
+        .map(|json| URL_SAFE_NO_PAD.encode(json))
+        .map_err(|err| OAuth2Error::internal(format!("failed to encode proof: {err}")))
+}
+
+fn crypto_error(err: JsValue, default: &str) -> OAuth2Error {
+    OAuth2Error::internal(
+        err.dyn_ref::<js_sys::Error>()
+            .map(|err| format!("{default}: {}", String::from(err.message())))
+            .unwrap_or_else(|| default.to_string()),
+    )
+}
+
+/// An error response, checked for requiring a nonce.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// An error performing a token request.
+#[derive(Debug)]
+pub(crate) enum DpopError<E> {
+    Proof(OAuth2Error),
+    Request(http::Error),
+    Http(E),
+}
+
+impl<E: Display> Display for DpopError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Proof(err) => write!(f, "failed to create DPoP proof: {err}"),
+            Self::Request(err) => write!(f, "invalid request: {err}"),
+            Self::Http(err) => write!(f, "request failed: {err}"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DpopError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Proof(err) => Some(err),
+            Self::Request(err) => Some(err),
+            Self::Http(err) => Some(err),
+        }
+    }
+}
+
+/// An HTTP client for token requests, adding a DPoP proof if a key is present.
+///
+/// If the server requires a nonce, the request is retried once, using the provided nonce.
+pub(crate) struct DpopClient<'a, H> {
+    http: &'a H,
+    key: Option<&'a DpopKey>,
+}
+
+impl<'a, H> DpopClient<'a, H> {
+    pub fn new(http: &'a H, key: Option<&'a DpopKey>) -> Self {
+        Self { http, key }
+    }
+
+    async fn send<'c>(&'c self, request: HttpRequest) -> Result<HttpResponse, DpopError<H::Error>>
+    where
+        H: AsyncHttpClient<'c>,
+    {
+        let url = request.uri().to_string();
+        let mut retry = true;
+
+        loop {
+            let mut builder = http::Request::builder()
+                .method(request.method().clone())
+                .uri(request.uri().clone())
+                .version(request.version());
+            if let Some(headers) = builder.headers_mut() {
+                *headers = request.headers().clone();
+            }
+            if let Some(key) = self.key {
+                let proof = key
+                    .proof(request.method().as_str(), &url, None)
+                    .await
+                    .map_err(DpopError::Proof)?;
+                builder = builder.header(DPOP_HEADER, proof);
+            }
+            let attempt = builder
+                .body(request.body().clone())
+                .map_err(DpopError::Request)?;
+
+            let response = self.http.call(attempt).await.map_err(DpopError::Http)?;
+
+            let nonce = response
+                .headers()
+                .get(DPOP_NONCE_HEADER)
+                .and_then(|nonce| nonce.to_str().ok());
+            if let (Some(key), Some(nonce)) = (self.key, nonce) {
+                let changed = key.set_nonce(&url, nonce);
+                if retry
+                    && changed
+                    && response.status() == StatusCode::BAD_REQUEST
+                    && requires_nonce(response.body())
+                {
+                    log::debug!("Server requires a DPoP nonce, retrying");
+                    retry = false;
+                    continue;
+                }
+            }
+
+            return Ok(response);
+        }
+    }
+}
+
+fn requires_nonce(body: &[u8]) -> bool {
+    serde_json::from_slice::<ErrorResponse>(body)
+        .is_ok_and(|response| response.error == USE_DPOP_NONCE)
+}
+
+impl<'c, H> AsyncHttpClient<'c> for DpopClient<'_, H>
+where
+    H: AsyncHttpClient<'c> + 'c,
+{
+    type Error = DpopError<H::Error>;
+    type Future = Pin<Box<dyn Future<Output = Result<HttpResponse, Self::Error>> + 'c>>;
+
+    fn call(&'c self, request: HttpRequest) -> Self::Future {
+        Box::pin(self.send(request))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+    use serde_json::Value;
+    use std::{cell::Cell, collections::HashSet, convert::Infallible};
+
+    pub(super) struct TestSigner;
+
+    #[async_trait(?Send)]
+    impl Signer for TestSigner {
+        async fn sign(&self, _: &[u8]) -> Result<Vec<u8>, OAuth2Error> {
+            Ok(b"signature".to_vec())
+        }
+    }
+
+    fn key() -> DpopKey {
+        DpopKey::test()
+    }
+
+    /// Decode the header and the claims of a proof.
+    fn decode(proof: &str) -> (Value, Value) {
+        let parts: Vec<_> = proof.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let decode = |part: &str| -> Value {
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).unwrap()).unwrap()
+        };
+        (decode(parts[0]), decode(parts[1]))
+    }
+
+    fn request(url: &str) -> HttpRequest {
+        http::Request::builder()
+            .method(http::Method::POST)
+            .uri(url)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(b"grant_type=refresh_token".to_vec())
+            .unwrap()
+    }
+
+    fn response(status: u16, nonce: Option<&str>, body: &str) -> HttpResponse {
+        let mut builder = http::Response::builder().status(status);
+        if let Some(nonce) = nonce {
+            builder = builder.header(DPOP_NONCE_HEADER, nonce);
+        }
+        builder.body(body.as_bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn proof_header() {
+        let proof = block_on(key().proof("POST", "https://issuer/token", None)).unwrap();
+        let (header, _) = decode(&proof);
+
+        assert_eq!(header["typ"], "dpop+jwt");
+        assert_eq!(header["alg"], "ES256");
+        assert_eq!(header["jwk"]["kty"], "EC");
+        assert_eq!(header["jwk"]["crv"], "P-256");
+        assert_eq!(header["jwk"]["x"], "x-coordinate");
+        assert_eq!(header["jwk"]["y"], "y-coordinate");
+        // the private key must never be part of the proof
+        assert!(header["jwk"].get("d").is_none());
+    }
+
+    #[test]
+    fn proof_claims() {
+        let key = key();
+        let proof =
+            block_on(key.proof("get", "https://api.example/resource?id=1#section", None)).unwrap();
+        let (_, claims) = decode(&proof);
+
+        assert_eq!(claims["htm"], "GET");
+        assert_eq!(claims["htu"], "https://api.example/resource");
+        assert!(claims["iat"].as_u64().unwrap() > 1_600_000_000);
+        assert!(!claims["jti"].as_str().unwrap().is_empty());
+        assert!(claims.get("ath").is_none());
+        assert!(claims.get("nonce").is_none());
+    }
+
+    #[test]
+    fn proof_unique_jti() {
+        let key = key();
+        let jtis: HashSet<_> = (0..10)
+            .map(|_| {
+                let proof = block_on(key.proof("POST", "https://issuer/token", None)).unwrap();
+                decode(&proof).1["jti"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(jtis.len(), 10);
+    }
+
+    #[test]
+    fn proof_access_token_hash() {
+        // example of RFC 9449, section 7.1
+        let proof = block_on(key().proof(
+            "GET",
+            "https://resource.example.org/protectedresource",
+            Some("Kz~8mXK1EalYznwH-LC-1fBAo.4Ljp~zsPE_NeO.gxU"),
+        ))
+        .unwrap();
+        let (_, claims) = decode(&proof);
+
+        assert_eq!(claims["ath"], "fUHyO2r2Z3DZ53EsNrWBb0xWXoaNy59IiKCAqksmQEo");
+    }
+
+    #[test]
+    fn nonce_per_origin() {
+        let key = key();
+        assert!(key.set_nonce("https://issuer/token", "n1"));
+        assert!(!key.set_nonce("https://issuer/token", "n1"));
+
+        let proof = block_on(key.proof("POST", "https://issuer/other?x=1", None)).unwrap();
+        assert_eq!(decode(&proof).1["nonce"], "n1");
+
+        let proof = block_on(key.proof("GET", "https://api.example/resource", None)).unwrap();
+        assert!(decode(&proof).1.get("nonce").is_none());
+
+        assert!(key.set_nonce("https://issuer/token", "n2"));
+        let proof = block_on(key.proof("POST", "https://issuer/token", None)).unwrap();
+        assert_eq!(decode(&proof).1["nonce"], "n2");
+    }
+
+    #[test]
+    fn retry_with_nonce_once() {
+        let key = key();
+        let proofs = RefCell::new(Vec::new());
+        // a stand-in token endpoint, requiring a nonce
+        let endpoint = |request: HttpRequest| {
+            let proof = request.headers()[DPOP_HEADER].to_str().unwrap().to_string();
+            let nonce = decode(&proof).1.get("nonce").cloned();
+            proofs.borrow_mut().push(proof);
+            async move {
+                Ok::<_, Infallible>(match nonce {
+                    Some(nonce) if nonce == "n1" => {
+                        response(200, None, r#"{"access_token":"token"}"#)
+                    }
+                    _ => response(400, Some("n1"), r#"{"error":"use_dpop_nonce"}"#),
+                })
+            }
+        };
+
+        let client = DpopClient::new(&endpoint, Some(&key));
+        let response = block_on(client.call(request("https://issuer/token"))).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let proofs = proofs.borrow();
+        assert_eq!(proofs.len(), 2);
+        assert!(decode(&proofs[0]).1.get("nonce").is_none());
+        assert_eq!(decode(&proofs[1]).1["nonce"], "n1");
+        // the nonce is kept for further requests
+        let proof = block_on(key.proof("POST", "https://issuer/token", None)).unwrap();
+        assert_eq!(decode(&proof).1["nonce"], "n1");
+    }
+
+    #[test]
+    fn retry_exactly_once() {
+        let key = key();
+        let calls = Cell::new(0);
+        // a stand-in token endpoint, always requiring a new nonce
+        let endpoint = |_: HttpRequest| {
+            calls.set(calls.get() + 1);
+            let nonce = format!("n{}", calls.get());
+            async move {
+                Ok::<_, Infallible>(response(400, Some(&nonce), r#"{"error":"use_dpop_nonce"}"#))
+            }
+        };
+
+        let client = DpopClient::new(&endpoint, Some(&key));
+        let response = block_on(client.call(request("https://issuer/token"))).unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn no_retry_for_other_errors() {
+        let key = key();
+        let calls = Cell::new(0);
+        let endpoint = |_: HttpRequest| {
+            calls.set(calls.get() + 1);
+            async { Ok::<_, Infallible>(response(400, Some("n1"), r#"{"error":"invalid_grant"}"#)) }
+        };
+
+        let client = DpopClient::new(&endpoint, Some(&key));
+        let response = block_on(client.call(request("https://issuer/token"))).unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn without_key() {
+        let endpoint = |request: HttpRequest| {
+            let proof = request.headers().get(DPOP_HEADER).cloned();
+            async move {
+                assert!(proof.is_none());
+                Ok::<_, Infallible>(response(200, None, "{}"))
+            }
+        };
+
+        let client = DpopClient::new(&endpoint, None);
+        let response = block_on(client.call(request("https://issuer/token"))).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,49 +1,253 @@
+use super::Error;
 use crate::context::OAuth2Context;
 use core::fmt::{Display, Formatter};
+use std::rc::Rc;
 
-/// An error with the OAuth2 agent
-#[derive(Debug)]
-pub enum OAuth2Error {
+/// The kind of an [`OAuth2Error`]
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
     /// Not initialized
     NotInitialized,
+    /// There is no authenticated session
+    NotAuthenticated,
     /// Configuration error
-    Configuration(String),
+    Configuration,
     /// Failed to start login
-    StartLogin(String),
+    StartLogin,
+    /// The user (or the issuer) denied the authorization request, e.g. by cancelling the login
+    AccessDenied,
+    /// The issuer requires the user to interact, e.g. when using `prompt=none`
+    InteractionRequired,
+    /// The issuer rejected the authorization request for another reason
+    Authorization,
+    /// The state of the authorization response didn't match the request
+    StateMismatch,
     /// Failed to handle login result
-    LoginResult(String),
-    /// Failed to handle token refresh
-    Refresh(String),
+    LoginResult,
+    /// A token returned by the issuer is invalid, e.g. an invalid ID token signature
+    InvalidToken,
+    /// The authentication doesn't satisfy the requested maximum age or context class
+    InsufficientAuthentication,
+    /// The issuer rejected the token refresh, e.g. due to an expired refresh token
+    Refresh,
+    /// Failed to reach the issuer
+    Network,
+    /// The issuer is temporarily unavailable
+    Unavailable,
+    /// The browser blocked opening the login popup
+    PopupBlocked,
+    /// The user closed the login popup before the login was completed
+    PopupClosed,
     /// Failing storing information
-    Storage(String),
+    Storage,
     /// Internal error
-    Internal(String),
+    Internal,
+    /// Failed to communicate with the agent
+    Agent,
+}
+
+impl ErrorKind {
+    /// Evaluate the kind from an error code, as defined by RFC 6749 and OpenID Connect.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "access_denied" => Some(Self::AccessDenied),
+            "login_required"
+            | "interaction_required"
+            | "consent_required"
+            | "account_selection_required" => Some(Self::InteractionRequired),
+            "temporarily_unavailable" | "server_error" => Some(Self::Unavailable),
+            _ => None,
+        }
+    }
+
+    /// Check if the error is temporary, and the operation might succeed when being retried.
+    pub fn is_temporary(&self) -> bool {
+        matches!(self, Self::Network | Self::Unavailable)
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotInitialized => "not initialized",
+            Self::NotAuthenticated => "not authenticated",
+            Self::Configuration => "configuration error",
+            Self::StartLogin => "start login error",
+            Self::AccessDenied => "access denied",
+            Self::InteractionRequired => "interaction required",
+            Self::Authorization => "authorization error",
+            Self::StateMismatch => "state mismatch",
+            Self::LoginResult => "login result",
+            Self::InvalidToken => "invalid token",
+            Self::InsufficientAuthentication => "insufficient authentication",
+            Self::Refresh => "refresh error",
+            Self::Network => "network error",
+            Self::Unavailable => "issuer unavailable",
+            Self::PopupBlocked => "login popup was blocked",
+            Self::PopupClosed => "login popup was closed",
+            Self::Storage => "storage error",
+            Self::Internal => "internal error",
+            Self::Agent => "agent error",
+        })
+    }
+}
+
+/// An error with the OAuth2 agent
+///
+/// Errors reported by the issuer carry the error code, description, and URI of the error
+/// response (see [RFC 6749, Section 4.1.2.1](https://www.rfc-editor.org/rfc/rfc6749#section-4.1.2.1)).
+#[derive(Clone, Debug)]
+pub struct OAuth2Error {
+    /// The kind of error
+    pub kind: ErrorKind,
+    /// The error code, as reported by the issuer
+    pub code: Option<String>,
+    /// A human-readable description of the error
+    pub description: Option<String>,
+    /// A URI, pointing to more information about the error
+    pub uri: Option<String>,
+    /// The underlying cause
+    pub source: Option<Rc<dyn std::error::Error>>,
 }
 
+impl PartialEq for OAuth2Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+            && self.code == other.code
+            && self.description == other.description
+            && self.uri == other.uri
+            && match (&self.source, &other.source) {
+                (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for OAuth2Error {}
+
 impl Display for OAuth2Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::NotInitialized => f.write_str("not initialized"),
-            Self::Configuration(err) => write!(f, "configuration error: {err}"),
-            Self::StartLogin(err) => write!(f, "start login error: {err}"),
-            Self::LoginResult(err) => write!(f, "login result: {err}"),
-            Self::Refresh(err) => write!(f, "refresh error: {err}"),
-            Self::Storage(err) => write!(f, "storage error: {err}"),
-            Self::Internal(err) => write!(f, "internal error: {err}"),
+        write!(f, "{}", self.kind)?;
+        if let Some(code) = &self.code {
+            write!(f, " ({code})")?;
         }
+        if let Some(description) = &self.description {
+            write!(f, ": {description}")?;
+        }
+        Ok(())
     }
 }
 
-impl std::error::Error for OAuth2Error {}
+impl std::error::Error for OAuth2Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref()
+    }
+}
+
+impl From<Error> for OAuth2Error {
+    fn from(err: Error) -> Self {
+        Self::new(ErrorKind::Agent)
+            .with_description(err.to_string())
+            .with_source(err)
+    }
+}
 
 impl From<OAuth2Error> for OAuth2Context {
     fn from(err: OAuth2Error) -> Self {
-        OAuth2Context::Failed(err.to_string())
+        OAuth2Context::Failed(err)
     }
 }
 
 impl OAuth2Error {
+    /// Create a new error of the provided kind.
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            code: None,
+            description: None,
+            uri: None,
+            source: None,
+        }
+    }
+
+    /// Create an error from an error response of the issuer.
+    ///
+    /// The kind is evaluated from the code, falling back to [`ErrorKind::Authorization`].
+    pub fn from_response(
+        code: impl Into<String>,
+        description: Option<String>,
+        uri: Option<String>,
+    ) -> Self {
+        let code = code.into();
+        Self {
+            kind: ErrorKind::from_code(&code).unwrap_or(ErrorKind::Authorization),
+            code: Some(code),
+            description,
+            uri,
+            source: None,
+        }
+    }
+
+    /// Set the error code
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// Set the description
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Set the error URI
+    pub fn with_uri(mut self, uri: impl Into<String>) -> Self {
+        self.uri = Some(uri.into());
+        self
+    }
+
+    /// Set the underlying cause
+    pub fn with_source(mut self, source: impl std::error::Error + 'static) -> Self {
+        self.source = Some(Rc::new(source));
+        self
+    }
+
+    /// Check if the error is temporary, and the operation might succeed when being retried.
+    pub fn is_temporary(&self) -> bool {
+        self.kind.is_temporary()
+    }
+
+    pub(crate) fn not_initialized() -> Self {
+        Self::new(ErrorKind::NotInitialized)
+    }
+
+    pub(crate) fn configuration(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Configuration).with_description(description)
+    }
+
+    pub(crate) fn start_login(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::StartLogin).with_description(description)
+    }
+
+    pub(crate) fn login_result(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::LoginResult).with_description(description)
+    }
+
+    pub(crate) fn refresh(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Refresh).with_description(description)
+    }
+
+    pub(crate) fn storage(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Storage).with_description(description)
+    }
+
+    pub(crate) fn internal(description: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Internal).with_description(description)
+    }
+
     pub(crate) fn storage_key_empty(key: impl Display) -> Self {
-        Self::Storage(format!("Missing value for key: {key}"))
+        Self::storage(format!("Missing value for key: {key}"))
     }
 }
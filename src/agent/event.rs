@@ -0,0 +1,138 @@
+//! Events, emitted by the agent during the lifecycle of a session.
+
+use super::OAuth2Error;
+use crate::context::{Authentication, Reason};
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+use yew::Callback;
+
+/// An event of the session lifecycle.
+///
+/// In contrast to the [`crate::context::OAuth2Context`], which only reflects the current state,
+/// events report what happened. This can be used for audit logging, or re-authenticating
+/// long-lived connections (like websockets) once the token was refreshed.
+#[non_exhaustive]
+#[derive(Clone, Debug)]
+pub enum AuthEvent {
+    /// The agent was configured, and the client created.
+    Configured,
+    /// Failed to configure the agent.
+    ConfigurationFailed(OAuth2Error),
+    /// A login flow was started.
+    LoginStarted,
+    /// A login failed, or could not be started.
+    LoginFailed(OAuth2Error),
+    /// A session was established, either by a login or by restoring an existing session.
+    LoggedIn(Authentication),
+    /// The token was refreshed.
+    TokenRefreshed(Authentication),
+    /// Refreshing the token failed.
+    RefreshFailed {
+        /// The error of the refresh attempt.
+        error: OAuth2Error,
+        /// The delay until the refresh is retried, [`None`] if it won't be retried.
+        retry: Option<Duration>,
+    },
+    /// The session expired, and could not be refreshed.
+    SessionExpired,
+    /// The user logged out.
+    LoggedOut,
+    /// The session is about to end, due to the idle timeout or the session limit.
+    ///
+    /// The session ends once the remaining time has passed, unless the user is active again
+    /// (in the case of the idle timeout).
+    SessionEnding {
+        /// The reason the session will end.
+        reason: Reason,
+        /// The time remaining until the session ends.
+        remaining: Duration,
+    },
+    /// The access token expired while the browser is offline.
+    ///
+    /// The token is refreshed once the browser is online again. Until then, the expired token is
+    /// no longer provided by [`crate::context::LatestAccessToken`], and requesting a token fails.
+    TokenExpired,
+    /// The browser went offline, refreshing the token is deferred until it is online again.
+    Offline,
+    /// The browser is online again.
+    Online,
+}
+
+#[derive(Debug, Default)]
+struct Subscribers {
+    next_id: usize,
+    callbacks: Vec<(usize, Callback<AuthEvent>)>,
+}
+
+/// A handle to subscribe to the [`AuthEvent`]s of an agent.
+///
+/// This is provided as context by the [`crate::components::context::OAuth2`] component, and
+/// can be used with the [`crate::hook::use_auth_events`] hook.
+#[derive(Clone, Debug, Default)]
+pub struct AuthEvents {
+    subscribers: Rc<RefCell<Subscribers>>,
+}
+
+impl PartialEq for AuthEvents {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.subscribers, &other.subscribers)
+    }
+}
+
+impl AuthEvents {
+    /// Subscribe to events.
+    ///
+    /// The callback is invoked for every event, until the returned subscription is dropped.
+    #[must_use = "dropping the subscription unsubscribes the callback"]
+    pub fn subscribe(&self, callback: Callback<AuthEvent>) -> EventSubscription {
+        let mut subscribers = self.subscribers.borrow_mut();
+
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.callbacks.push((id, callback));
+
+        EventSubscription {
+            subscribers: Rc::downgrade(&self.subscribers),
+            id,
+        }
+    }
+
+    /// Emit an event to all subscribers.
+    pub(crate) fn emit(&self, event: AuthEvent) {
+        log::debug!("Emitting event: {event:?}");
+
+        // release the borrow before calling out, subscribers might (un)subscribe
+        let callbacks: Vec<_> = self
+            .subscribers
+            .borrow()
+            .callbacks
+            .iter()
+            .map(|(_, callback)| callback.clone())
+            .collect();
+
+        for callback in callbacks {
+            callback.emit(event.clone());
+        }
+    }
+}
+
+/// A subscription to [`AuthEvent`]s, unsubscribing when dropped.
+#[derive(Debug)]
+pub struct EventSubscription {
+    subscribers: Weak<RefCell<Subscribers>>,
+    id: usize,
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers
+                .borrow_mut()
+                .callbacks
+                .retain(|(id, _)| *id != self.id);
+        }
+    }
+}
@@ -1,29 +1,66 @@
 //! The agent, working in the background to manage the session and refresh tokens.
 pub mod client;
 
+mod activity;
+mod callback;
 mod config;
+mod deadline;
+mod dpop;
 mod error;
+mod event;
+mod online;
 mod ops;
+mod par;
+mod rar;
+mod refresh;
+mod retry;
+mod silent;
 mod state;
+mod storage;
+mod sync;
+mod tasks;
+mod wakeup;
 
 pub use client::*;
+pub use dpop::{DPOP_HEADER, DPOP_NONCE_HEADER, DpopKey};
 pub use error::*;
+pub use event::*;
 pub use ops::*;
+pub use rar::{AuthorizationDetail, AuthorizationDetailsFields};
+pub use refresh::*;
+pub use retry::*;
+pub use silent::SilentLoginOptions;
 pub use state::LoginState;
+pub use storage::*;
 
 pub(crate) use config::*;
+pub(crate) use deadline::*;
+pub(crate) use dpop::DpopClient;
+pub(crate) use rar::authorization_details_param;
 
+use crate::config::ResponseMode;
 use crate::context::{Authentication, OAuth2Context, Reason};
-use gloo_storage::{SessionStorage, Storage};
+use activity::*;
+use futures::{
+    FutureExt,
+    future::{AbortHandle, Abortable, LocalBoxFuture},
+};
 use gloo_timers::callback::Timeout;
 use gloo_utils::{history, window};
 use js_sys::Date;
 use log::error;
 use num_traits::cast::ToPrimitive;
+use online::*;
 use reqwest::Url;
 use state::*;
-use std::{cmp::min, collections::HashMap, fmt::Debug, time::Duration};
-use tokio::sync::mpsc::{Receiver, Sender, channel};
+use std::{cmp::min, collections::HashMap, fmt::Debug, rc::Rc, time::Duration};
+use sync::*;
+use tasks::*;
+use tokio::sync::{
+    mpsc::{Receiver, Sender, WeakSender, channel},
+    oneshot,
+};
+use wakeup::*;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::spawn_local;
 use yew::Callback;
@@ -72,7 +109,7 @@ use yew::Callback;
 /// **NOTE:** As a summary, setting only the `redirect_url` will not be sufficient. The "post login redirect callback" must
 /// also be implemented or the `yew-nested-router`feature used. Otherwise, the user would simply end up on the page defined by
 /// `redirect_url`, which in most cases is not what one would expect.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 #[non_exhaustive]
 pub struct LoginOptions {
     /// Additional query parameters sent to the issuer.
@@ -91,6 +128,27 @@ pub struct LoginOptions {
     ///
     /// If `None`, disables post-login redirect.
     pub post_login_redirect_callback: Option<Callback<String>>,
+
+    /// Scopes to request, instead of the scopes configured for the session.
+    pub scopes: Option<Vec<String>>,
+
+    /// The maximum age of the authentication, requesting the user to log in again (`prompt=login`).
+    ///
+    /// This requires OpenID Connect, and is verified using the `auth_time` claim of the ID token.
+    pub max_age: Option<Duration>,
+
+    /// The requested authentication context class references (`acr_values`).
+    ///
+    /// This requires OpenID Connect, and is verified using the `acr` claim of the ID token.
+    pub acr_values: Vec<String>,
+
+    /// The response mode to request, overriding the response mode of the client configuration.
+    pub response_mode: Option<ResponseMode>,
+
+    /// The authorization details to request (`authorization_details`), in addition to the scopes.
+    ///
+    /// The details granted by the issuer are available as [`Authentication::authorization_details`].
+    pub authorization_details: Vec<AuthorizationDetail>,
 }
 
 impl LoginOptions {
@@ -122,6 +180,42 @@ impl LoginOptions {
         self
     }
 
+    /// Set the scopes to request, overriding the configured scopes
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scopes = Some(scopes.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set the maximum age of the authentication
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set the requested authentication context class references
+    pub fn with_acr_values(
+        mut self,
+        acr_values: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.acr_values = acr_values.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the response mode
+    pub fn with_response_mode(mut self, response_mode: ResponseMode) -> Self {
+        self.response_mode = Some(response_mode);
+        self
+    }
+
+    /// Set the authorization details to request
+    pub fn with_authorization_details(
+        mut self,
+        authorization_details: impl IntoIterator<Item = AuthorizationDetail>,
+    ) -> Self {
+        self.authorization_details = authorization_details.into_iter().collect();
+        self
+    }
+
     /// Set a callback for post-login redirect
     pub fn with_redirect_callback(mut self, redirect_callback: Callback<String>) -> Self {
         self.post_login_redirect_callback = Some(redirect_callback);
@@ -165,15 +259,187 @@ impl LogoutOptions {
     }
 }
 
+/// An optional channel, reporting the outcome of an operation back to the caller.
+#[doc(hidden)]
+pub type Reply<T> = Option<oneshot::Sender<Result<T, OAuth2Error>>>;
+
+/// The popup window for a login, opened by the caller, or the reason it couldn't be opened.
+#[doc(hidden)]
+pub type PopupWindow = Result<web_sys::Window, OAuth2Error>;
+
+/// Options for refreshing the token
+///
+///**NOTE**: This is a non-exhaustive struct. See [`LoginOptions`] for an example on how to work with this.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshOptions {
+    /// Scopes to request. If empty, the issuer grants the scopes of the original request.
+    pub scopes: Vec<String>,
+
+    /// Additional parameters sent to the token endpoint.
+    pub params: HashMap<String, String>,
+
+    /// Authorization details to request. If empty, the issuer grants the details of the original
+    /// request.
+    pub authorization_details: Vec<AuthorizationDetail>,
+}
+
+impl RefreshOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the scopes to request
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the authorization details to request
+    pub fn with_authorization_details(
+        mut self,
+        authorization_details: impl IntoIterator<Item = AuthorizationDetail>,
+    ) -> Self {
+        self.authorization_details = authorization_details.into_iter().collect();
+        self
+    }
+
+    /// Add a parameter for the refresh request
+    pub fn add_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(key.into(), value.into());
+        self
+    }
+}
+
 #[doc(hidden)]
 pub enum Msg<C>
 where
     C: Client,
 {
-    Configure(AgentConfiguration<C>),
-    StartLogin(Option<LoginOptions>),
-    Logout(Option<LogoutOptions>),
-    Refresh,
+    Configure(Box<AgentConfiguration<C>>, Option<State>, Reply<()>),
+    StartLogin(Option<LoginOptions>, Reply<()>),
+    StartLoginPopup(LoginOptions, PopupWindow, Reply<Authentication>),
+    StepUp(Vec<String>, Vec<AuthorizationDetail>, Reply<()>),
+    StepUpPopup(
+        Vec<String>,
+        Vec<AuthorizationDetail>,
+        PopupWindow,
+        Reply<Authentication>,
+    ),
+    Reauthenticate(Duration, Vec<String>, PopupWindow, Reply<Authentication>),
+    PopupCallback(Box<PopupCallback<C>>),
+    Sync(Box<SyncMessage<C::SessionState>>),
+    Logout(Option<LogoutOptions>, Reply<()>),
+    Refresh(Option<RefreshOptions>, Reply<Authentication>),
+    CheckExpiry,
+    CheckSession,
+    Activity(f64),
+    ExtendSession(Reply<Authentication>),
+    Connectivity(bool),
+    AccessToken(Reply<Authentication>),
+    Reset(Reply<()>),
+    RefreshResult(Box<Result<(OAuth2Context, C::SessionState), OAuth2Error>>),
+}
+
+/// The maximum time to wait for with a single timer.
+///
+/// Long token lifetimes are split into multiple timers, re-checking the time in between.
+const MAX_TIMER_CHUNK: Duration = Duration::from_secs(60 * 60);
+
+/// The interval of sharing the user's activity with other tabs.
+const ACTIVITY_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Points in time (seconds since the epoch) relevant to the expiration of the session.
+#[derive(Clone, Copy, Debug)]
+struct Expiry {
+    /// The time to refresh the token, before it expires.
+    refresh_at: f64,
+    /// The time the token expires.
+    expires: f64,
+    /// Only refresh when the token is being requested.
+    lazy: bool,
+}
+
+/// The session, while refreshing the token is deferred as the browser is offline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Deferred {
+    /// The refresh token expired, the session can't be refreshed once online again.
+    RefreshExpired,
+    /// The session can still be refreshed, check again at the next deadline, if any.
+    Pending {
+        /// If the access token expired.
+        token_expired: bool,
+        next: Option<f64>,
+    },
+}
+
+impl Deferred {
+    fn evaluate(now: f64, expires: f64, refresh_expires: Option<f64>) -> Self {
+        if refresh_expires.is_some_and(|refresh_expires| now >= refresh_expires) {
+            return Self::RefreshExpired;
+        }
+
+        let token_expired = now >= expires;
+        let next = match token_expired {
+            true => refresh_expires,
+            false => Some(refresh_expires.map_or(expires, |refresh| refresh.min(expires))),
+        };
+
+        Self::Pending {
+            token_expired,
+            next,
+        }
+    }
+}
+
+/// A refresh of the token, currently in progress.
+struct Refreshing {
+    /// The refresh token used for the request.
+    refresh_token: String,
+    options: Option<RefreshOptions>,
+    /// Callers waiting for the outcome.
+    waiters: Vec<oneshot::Sender<Result<Authentication, OAuth2Error>>>,
+    /// Requests with different options, to be performed afterwards.
+    deferred: Vec<(Option<RefreshOptions>, Reply<Authentication>)>,
+}
+
+/// Close the popup window, in case it was opened but isn't used.
+fn close_popup(popup: PopupWindow) {
+    if let Ok(popup) = popup {
+        popup.close().ok();
+    }
+}
+
+/// Send the outcome of an operation, in case the caller is waiting for it.
+fn respond<T>(reply: Reply<T>, result: Result<T, OAuth2Error>) {
+    if let Some(reply) = reply {
+        // the caller might not be interested anymore
+        let _ = reply.send(result);
+    }
+}
+
+/// The information required to complete a login, once the authorization response was received.
+#[doc(hidden)]
+pub struct LoginTransaction<C>
+where
+    C: Client,
+{
+    csrf_token: String,
+    login_state: C::LoginState,
+    redirect_url: Url,
+    /// The scopes requested by the login.
+    scopes: Vec<String>,
+}
+
+/// The outcome of a popup based login.
+#[doc(hidden)]
+pub struct PopupCallback<C>
+where
+    C: Client,
+{
+    response: Result<Url, OAuth2Error>,
+    transaction: LoginTransaction<C>,
+    reply: Reply<Authentication>,
 }
 
 /// The agent handling the OAuth2/OIDC state
@@ -183,6 +449,9 @@ where
     C: Client,
 {
     tx: Sender<Msg<C>>,
+    events: AuthEvents,
+    deadlines: SessionDeadlines,
+    shutdown: AbortHandle,
 }
 
 impl<C> Agent<C>
@@ -194,11 +463,71 @@ where
         F: Fn(OAuth2Context) + 'static,
     {
         let (tx, rx) = channel(128);
+        let events = AuthEvents::default();
+        let deadlines = SessionDeadlines::default();
+
+        let inner = InnerAgent::new(
+            SelfSender(tx.downgrade()),
+            state_callback,
+            events.clone(),
+            deadlines.clone(),
+        );
+        let shutdown = inner.spawn(rx);
+
+        Self {
+            tx,
+            events,
+            deadlines,
+            shutdown,
+        }
+    }
+
+    pub(crate) fn deadlines(&self) -> SessionDeadlines {
+        self.deadlines.clone()
+    }
+
+    /// Shut down the agent.
+    ///
+    /// This stops all timers, and cancels all requests in progress. Afterwards, all operations
+    /// will fail with [`Error::NoAgent`].
+    ///
+    /// This is done by the [`crate::components::context::OAuth2`] component when it is destroyed.
+    /// Otherwise, the agent will shut down once all of its handles are dropped.
+    pub fn shutdown(&self) {
+        log::debug!("Shutting down agent");
+        self.shutdown.abort();
+    }
+
+    /// Subscribe to the [`AuthEvent`]s of this agent.
+    ///
+    /// The callback is invoked for every event, until the returned subscription is dropped.
+    #[must_use = "dropping the subscription unsubscribes the callback"]
+    pub fn subscribe(&self, callback: Callback<AuthEvent>) -> EventSubscription {
+        self.events.subscribe(callback)
+    }
+
+    /// Get a handle for subscribing to the events of this agent.
+    pub fn events(&self) -> AuthEvents {
+        self.events.clone()
+    }
+}
 
-        let inner = InnerAgent::new(tx.clone(), state_callback);
-        inner.spawn(rx);
+/// A handle for the inner agent, sending messages to itself.
+///
+/// This doesn't keep the channel open, so that the agent shuts down once all [`Agent`] handles
+/// are dropped.
+#[derive(Clone)]
+struct SelfSender<C: Client>(WeakSender<Msg<C>>);
+
+impl<C: Client> SelfSender<C> {
+    fn try_send(&self, msg: Msg<C>) -> Result<(), Error> {
+        self.0.upgrade().ok_or(Error::NoAgent)?.try_send(msg)?;
+        Ok(())
+    }
 
-        Self { tx }
+    async fn send(&self, msg: Msg<C>) -> Result<(), Error> {
+        self.0.upgrade().ok_or(Error::NoAgent)?.send(msg).await?;
+        Ok(())
     }
 }
 
@@ -207,49 +536,147 @@ pub struct InnerAgent<C>
 where
     C: Client,
 {
-    tx: Sender<Msg<C>>,
+    tx: SelfSender<C>,
     state_callback: Callback<OAuth2Context>,
+    events: AuthEvents,
     config: Option<InnerConfig>,
     client: Option<C>,
     state: OAuth2Context,
     session_state: Option<C::SessionState>,
     timeout: Option<Timeout>,
+    expiry: Option<Expiry>,
+    _wakeup: Wakeup,
+    _connectivity: Connectivity,
+    sync: Option<TabSync>,
+    activity: Option<Activity>,
+    /// Timer for ending the session, when exceeding the idle timeout or session limit.
+    session_timeout: Option<Timeout>,
+    /// If a warning about the session ending was already emitted.
+    session_warned: bool,
+    deadlines: SessionDeadlines,
+    refresh_attempt: u32,
+    refreshing: Option<Refreshing>,
+    /// If the access token expired while refreshing it was deferred.
+    token_expired: bool,
+    /// Scopes requested by the last login, but not granted by the issuer.
+    denied_scopes: Vec<String>,
+    /// Tasks in progress, aborted when the agent is dropped.
+    tasks: Tasks,
+    /// The most recent configuration, required for resetting the agent.
+    configuration: Option<AgentConfiguration<C>>,
+    /// The authorization response, taken from the URL when configuring the agent.
+    response: Option<State>,
 }
 
 #[doc(hidden)]
 #[derive(Clone, Debug)]
 pub struct InnerConfig {
+    namespace: Option<String>,
     scopes: Vec<String>,
-    grace_period: Duration,
+    refresh_policy: Rc<dyn RefreshPolicy>,
     max_expiration: Option<Duration>,
     audience: Option<String>,
     default_login_options: Option<LoginOptions>,
     default_logout_options: Option<LogoutOptions>,
+    silent_login: Option<SilentLoginOptions>,
+    state_store: Rc<dyn StateStore>,
+    token_store: Option<Rc<dyn StateStore>>,
+    sync_tabs: bool,
+    refresh_retry: RetryOptions,
+    reconsent: bool,
+    /// The maximum authentication age of the current login.
+    max_age: Option<Duration>,
+    /// The authentication context class references of the current login.
+    acr_values: Vec<String>,
+    /// The response mode of the current login, overriding the one of the client.
+    response_mode: Option<ResponseMode>,
+    /// The authorization details of the current login.
+    authorization_details: Vec<AuthorizationDetail>,
+    idle_timeout: Option<Duration>,
+    session_limit: Option<Duration>,
+    session_warning: Option<Duration>,
+}
+
+impl InnerConfig {
+    /// The configuration for a login, applying the login options.
+    fn for_login(&self, options: Option<&LoginOptions>) -> Self {
+        let mut config = self.clone();
+        if let Some(options) = options {
+            if let Some(scopes) = &options.scopes {
+                config.scopes = scopes.clone();
+            }
+            config.max_age = options.max_age;
+            config.acr_values = options.acr_values.clone();
+            config.response_mode = options.response_mode;
+            config.authorization_details = options.authorization_details.clone();
+        }
+        config
+    }
 }
 
 impl<C> InnerAgent<C>
 where
     C: Client,
 {
-    pub fn new<F>(tx: Sender<Msg<C>>, state_callback: F) -> Self
+    fn new<F>(
+        tx: SelfSender<C>,
+        state_callback: F,
+        events: AuthEvents,
+        deadlines: SessionDeadlines,
+    ) -> Self
     where
         F: Fn(OAuth2Context) + 'static,
     {
+        let wakeup = {
+            let tx = tx.clone();
+            Wakeup::new(move || {
+                let _ = tx.try_send(Msg::CheckExpiry);
+            })
+        };
+        let connectivity = {
+            let tx = tx.clone();
+            Connectivity::new(move |online| {
+                let _ = tx.try_send(Msg::Connectivity(online));
+            })
+        };
+
         Self {
             tx,
             state_callback: Callback::from(state_callback),
+            events,
             client: None,
             config: None,
             state: OAuth2Context::NotInitialized,
             session_state: None,
             timeout: None,
+            expiry: None,
+            _wakeup: wakeup,
+            _connectivity: connectivity,
+            sync: None,
+            activity: None,
+            session_timeout: None,
+            session_warned: false,
+            deadlines,
+            refresh_attempt: 0,
+            token_expired: false,
+            refreshing: None,
+            denied_scopes: vec![],
+            tasks: Tasks::default(),
+            configuration: None,
+            response: None,
         }
     }
 
-    fn spawn(self, rx: Receiver<Msg<C>>) {
+    /// Spawn the agent, returning a handle to shut it down.
+    fn spawn(self, rx: Receiver<Msg<C>>) -> AbortHandle {
+        let (handle, registration) = AbortHandle::new_pair();
         spawn_local(async move {
-            self.run(rx).await;
-        })
+            // aborting drops the agent, cancelling its timers and tasks
+            if Abortable::new(self.run(rx), registration).await.is_err() {
+                log::debug!("Agent shut down");
+            }
+        });
+        handle
     }
 
     async fn run(mut self, mut rx: Receiver<Msg<C>>) {
@@ -266,218 +693,983 @@ where
 
     async fn process(&mut self, msg: Msg<C>) {
         match msg {
-            Msg::Configure(config) => self.configure(config).await,
-            Msg::StartLogin(login) => {
-                if let Err(err) = self.start_login(login) {
-                    // FIXME: need to report this somehow
-                    log::info!("Failed to start login: {err}");
+            Msg::Configure(config, response, reply) => {
+                let result = self.configure(*config, response).await;
+                respond(reply, result);
+            }
+            Msg::StartLogin(login, reply) => self.login(login, reply).await,
+            Msg::StartLoginPopup(login, popup, reply) => self.login_popup(login, popup, reply),
+            Msg::StepUp(scopes, details, reply) => match self.step_up(scopes, details) {
+                Ok(Some(options)) => self.login(Some(options), reply).await,
+                Ok(None) => respond(reply, Ok(())),
+                Err(err) => respond(reply, Err(err)),
+            },
+            Msg::StepUpPopup(scopes, details, popup, reply) => {
+                match self.step_up(scopes, details) {
+                    Ok(Some(options)) => self.login_popup(options, popup, reply),
+                    Ok(None) => {
+                        close_popup(popup);
+                        respond(reply, self.authentication());
+                    }
+                    Err(err) => {
+                        close_popup(popup);
+                        respond(reply, Err(err));
+                    }
                 }
             }
-            Msg::Logout(logout) => self.logout_opts(logout),
-            Msg::Refresh => self.refresh().await,
+            Msg::Reauthenticate(max_age, acr_values, popup, reply) => {
+                let options = self
+                    .config
+                    .as_ref()
+                    .and_then(|config| config.default_login_options.clone())
+                    .unwrap_or_default()
+                    .with_max_age(max_age)
+                    .with_acr_values(acr_values);
+                self.login_popup(options, popup, reply);
+            }
+            Msg::PopupCallback(callback) => self.popup_callback(*callback).await,
+            Msg::Sync(msg) => self.apply_sync(*msg),
+            Msg::Logout(logout, reply) => {
+                self.logout_opts(logout, Reason::Logout);
+                respond(reply, Ok(()));
+            }
+            Msg::Refresh(options, reply) => self.refresh(options, reply).await,
+            Msg::CheckExpiry => self.check_expiry().await,
+            Msg::CheckSession => self.check_session(),
+            Msg::Activity(at) => self.activity(at),
+            Msg::ExtendSession(reply) => self.extend_session(reply).await,
+            Msg::Connectivity(online) => self.connectivity(online).await,
+            Msg::AccessToken(reply) => self.access_token(reply).await,
+            Msg::Reset(reply) => {
+                let result = self.reset().await;
+                respond(reply, result);
+            }
+            Msg::RefreshResult(result) => self.refresh_result(*result),
         }
     }
 
     fn update_state(&mut self, state: OAuth2Context, session_state: Option<C::SessionState>) {
+        let state = self.with_session_start(state);
+        self.broadcast_state(&state, session_state.as_ref());
+        self.set_state(state, session_state);
+    }
+
+    /// Apply a new state, without informing other tabs.
+    fn set_state(&mut self, state: OAuth2Context, session_state: Option<C::SessionState>) {
         log::debug!("update state: {state:?}");
 
-        if let OAuth2Context::Authenticated(Authentication {
-            expires: Some(expires),
-            ..
-        }) = &state
-        {
-            let grace = self
-                .config
-                .as_ref()
-                .map(|c| c.grace_period)
-                .unwrap_or_default();
+        self.refresh_attempt = 0;
+        self.token_expired = false;
 
-            let mut expires = *expires;
-            if let Some(max) = self.config.as_ref().and_then(|cfg| cfg.max_expiration) {
-                // cap time the token expires by "max"
-                expires = min(expires, max.as_secs());
-            }
+        self.update_expiry(&state);
+        self.schedule_check(can_refresh(&state, self.config.as_ref()));
 
-            // get now as seconds
-            let now = Date::now() / 1000f64;
-            // get delta from now to expiration minus the grace period
-            let diff = expires as f64 - now - grace.as_secs_f64();
+        self.persist_session(&state, session_state.as_ref());
 
-            let tx = self.tx.clone();
-            if diff > 0f64 {
-                // while the API says millis is u32, internally it is i32
-                let millis = (diff * 1000f64).to_i32().unwrap_or(i32::MAX);
-                log::debug!("Starting timeout for: {millis}ms",);
-                self.timeout = Some(Timeout::new(millis as u32, move || {
-                    let _ = tx.try_send(Msg::Refresh);
-                }));
-            } else {
-                // token already expired
-                let _ = tx.try_send(Msg::Refresh);
-            }
-        } else {
-            self.timeout = None;
+        if let Some(event) = Self::transition_event(&self.state, &state) {
+            self.events.emit(event);
         }
 
         self.notify_state(state.clone());
 
         self.state = state;
         self.session_state = session_state;
+
+        self.check_session();
     }
 
-    fn notify_state(&self, state: OAuth2Context) {
-        self.state_callback.emit(state);
+    /// Set the start of the session, keeping it when refreshing the token.
+    fn with_session_start(&self, mut state: OAuth2Context) -> OAuth2Context {
+        if let OAuth2Context::Authenticated(auth) = &mut state {
+            if auth.session_start.is_none() {
+                auth.session_start = self
+                    .state
+                    .authentication()
+                    .and_then(|current| current.session_start)
+                    .or_else(|| (Date::now() / 1000f64).to_u64());
+            }
+        }
+        state
     }
 
-    /// Called once the configuration process has finished, applying the outcome.
-    async fn configured(&mut self, outcome: Result<(C, InnerConfig), OAuth2Error>) {
-        match outcome {
-            Ok((client, config)) => {
-                log::debug!("Client created");
+    /// Update the expiration of the session, from the provided state.
+    fn update_expiry(&mut self, state: &OAuth2Context) {
+        self.expiry = self.evaluate_expiry(state);
+        self.deadlines
+            .set_expires(self.expiry.map(|expiry| expiry.expires));
+    }
 
-                self.client = Some(client);
-                self.config = Some(config);
+    /// Evaluate the expiration of the session, based on the current configuration.
+    fn evaluate_expiry(&self, state: &OAuth2Context) -> Option<Expiry> {
+        let OAuth2Context::Authenticated(Authentication {
+            expires: Some(expires),
+            issued,
+            ..
+        }) = state
+        else {
+            return None;
+        };
 
-                if matches!(self.state, OAuth2Context::NotInitialized) {
-                    let detected = self.detect_state().await;
-                    log::debug!("Detected state: {detected:?}");
-                    match detected {
-                        Ok(true) => {
-                            if let Err(e) = self.post_login_redirect() {
-                                error!("Post-login redirect failed: {e}");
-                            }
-                        }
-                        Ok(false) => {
-                            self.update_state(
-                                OAuth2Context::NotAuthenticated {
-                                    reason: Reason::NewSession,
-                                },
-                                None,
-                            );
-                        }
-                        Err(err) => {
-                            self.update_state(err.into(), None);
-                        }
-                    }
-                }
-            }
-            Err(err) => {
-                log::debug!("Failed to configure client: {err}");
-                if matches!(self.state, OAuth2Context::NotInitialized) {
-                    self.update_state(err.into(), None);
-                }
-            }
+        let config = self.config.as_ref()?;
+
+        // sessions persisted by previous versions don't carry the time the token was issued
+        let issued = issued.map(|issued| issued as f64);
+        let mut expires = *expires as f64;
+        if let (Some(max), Some(issued)) = (config.max_expiration, issued) {
+            // cap the lifetime of the token by "max"
+            expires = expires.min(issued + max.as_secs_f64());
         }
+
+        let lifetime = TokenLifetime::new(issued.unwrap_or_else(|| Date::now() / 1000f64), expires);
+
+        Some(Expiry {
+            refresh_at: config.refresh_policy.refresh_at(&lifetime).min(expires),
+            expires,
+            lazy: config.refresh_policy.lazy(),
+        })
     }
 
-    async fn make_client(config: AgentConfiguration<C>) -> Result<(C, InnerConfig), OAuth2Error> {
-        let AgentConfiguration {
-            config,
-            scopes,
-            grace_period,
-            audience,
-            default_login_options,
-            default_logout_options,
-            max_expiration,
-        } = config;
+    /// Schedule the next check of the expiration.
+    ///
+    /// If the deadline has already passed, the check is performed right away.
+    fn schedule_check(&mut self, can_refresh: bool) {
+        self.timeout = None;
+
+        let Some(expiry) = self.expiry else {
+            return;
+        };
 
-        let client = C::from_config(config).await?;
+        // a session which can't be refreshed, is only checked for the actual expiration
+        let deadline = match (can_refresh, expiry.lazy) {
+            (true, false) => expiry.refresh_at,
+            // refreshed once the token is requested
+            (true, true) => return,
+            (false, _) => expiry.expires,
+        };
 
-        let inner = InnerConfig {
-            scopes,
-            grace_period,
-            audience,
-            default_login_options,
-            default_logout_options,
-            max_expiration,
+        let now = Date::now() / 1000f64;
+        let diff = duration_from_secs(deadline - now);
+
+        let tx = self.tx.clone();
+        if diff.is_zero() {
+            let _ = tx.try_send(Msg::CheckExpiry);
+        } else {
+            self.timeout = Some(chunked_timeout(diff, move || {
+                let _ = tx.try_send(Msg::CheckExpiry);
+            }));
+        }
+    }
+
+    /// Evaluate the deadline of the session, from the idle timeout and the session limit.
+    fn session_deadline(&self) -> Option<(f64, Reason)> {
+        let (idle, limit) = match (self.state.authentication(), &self.config) {
+            (Some(auth), Some(config)) => (
+                config
+                    .idle_timeout
+                    .zip(self.activity.as_ref())
+                    .map(|(timeout, activity)| (activity.last(), timeout)),
+                config
+                    .session_limit
+                    .zip(auth.session_start)
+                    .map(|(limit, start)| start as f64 + limit.as_secs_f64()),
+            ),
+            _ => (None, None),
         };
 
-        Ok((client, inner))
+        self.deadlines.set_limits(idle, limit);
+        self.deadlines.session_end()
     }
 
-    /// When initializing, try to detect the state from the URL and session state.
+    /// Extend the session, resetting the idle timeout and refreshing the token.
     ///
-    /// Returns `false` if there is no authentication state found and the result is final.
-    /// Otherwise, it returns `true` and spawns a request for e.g. a code exchange.
-    async fn detect_state(&mut self) -> Result<bool, OAuth2Error> {
-        let client = self.client.as_ref().ok_or(OAuth2Error::NotInitialized)?;
+    /// If there is no refresh token, this falls back to a silent login, if enabled.
+    async fn extend_session(&mut self, reply: Reply<Authentication>) {
+        if self.state.authentication().is_none() {
+            respond(reply, Err(OAuth2Error::new(ErrorKind::NotAuthenticated)));
+            return;
+        }
+
+        let now = Date::now() / 1000f64;
+        if let Some(activity) = &self.activity {
+            activity.record(now);
+        }
+        self.activity(now);
 
-        let state = if let Some(state) = Self::find_query_state() {
-            state
+        self.refresh(None, reply).await;
+    }
+
+    /// Check if the session exceeded the idle timeout or session limit, ending it if it did.
+    ///
+    /// Otherwise, a warning is emitted when the session is about to end, and the next check is
+    /// scheduled.
+    fn check_session(&mut self) {
+        self.session_timeout = None;
+
+        let Some((deadline, reason)) = self.session_deadline() else {
+            self.session_warned = false;
+            return;
+        };
+
+        let now = Date::now() / 1000f64;
+        if now >= deadline {
+            log::info!("Ending session: {reason:?}");
+            self.session_warned = false;
+            self.logout_opts(None, reason);
+            return;
+        }
+
+        let warning = self
+            .config
+            .as_ref()
+            .and_then(|config| config.session_warning)
+            .unwrap_or_default();
+        let warn_at = deadline - warning.as_secs_f64();
+
+        let next = if now >= warn_at {
+            if !self.session_warned {
+                self.session_warned = true;
+                self.events.emit(AuthEvent::SessionEnding {
+                    reason,
+                    remaining: duration_from_secs(deadline - now),
+                });
+            }
+            deadline
         } else {
-            // unable to get location and query
-            return Ok(false);
+            // there was activity after the warning
+            self.session_warned = false;
+            warn_at
         };
 
-        log::debug!("Found state: {state:?}",);
+        let tx = self.tx.clone();
+        self.session_timeout = Some(chunked_timeout(duration_from_secs(next - now), move || {
+            let _ = tx.try_send(Msg::CheckSession);
+        }));
+    }
 
-        if let Some(error) = state.error {
-            log::info!("Login error from server: {error}");
+    /// Handle (throttled) activity of the user in this tab.
+    fn activity(&mut self, at: f64) {
+        if let Some(sync) = &self.sync {
+            sync.send(&SyncMessage::<C::SessionState>::Activity { at });
+        }
+        if self.session_warned {
+            self.check_session();
+        }
+    }
 
-            // cleanup URL
-            Self::cleanup_url();
+    /// Track the activity of the user, if an idle timeout is configured.
+    fn setup_activity(&mut self) {
+        if self
+            .config
+            .as_ref()
+            .is_none_or(|config| config.idle_timeout.is_none())
+        {
+            self.activity = None;
+            return;
+        }
 
-            // error from the OAuth2 server
-            return Err(OAuth2Error::LoginResult(error));
+        if self.activity.is_none() {
+            let tx = self.tx.clone();
+            self.activity = Some(Activity::new(ACTIVITY_SYNC_INTERVAL, move |at| {
+                let _ = tx.try_send(Msg::Activity(at));
+            }));
         }
+    }
 
-        if let Some(code) = state.code {
-            // cleanup URL
-            Self::cleanup_url();
+    /// Check the expiration of the session, refreshing the token if required.
+    async fn check_expiry(&mut self) {
+        let Some(expiry) = self.expiry else {
+            return;
+        };
+        if self.refreshing.is_some() {
+            // the outcome will re-schedule the check
+            return;
+        }
 
-            match state.state {
-                None => {
-                    return Err(OAuth2Error::LoginResult(
-                        "Missing state from server".to_string(),
-                    ));
-                }
-                Some(state) => {
-                    let stored_state = get_from_store(STORAGE_KEY_CSRF_TOKEN)?;
+        let now = Date::now() / 1000f64;
+        let can_refresh = can_refresh(&self.state, self.config.as_ref());
 
-                    if state != stored_state {
-                        return Err(OAuth2Error::LoginResult("State mismatch".to_string()));
-                    }
-                }
-            }
+        if self.refresh_attempt > 0 && now < expiry.expires {
+            // waiting for a retry, which has its own timer
+            return;
+        }
 
-            let state: C::LoginState =
-                SessionStorage::get(STORAGE_KEY_LOGIN_STATE).map_err(|err| {
-                    OAuth2Error::Storage(format!("Failed to load login state: {err}"))
-                })?;
+        if can_refresh && !expiry.lazy && now >= expiry.refresh_at {
+            log::debug!("Token is about to expire, refreshing");
+            self.refresh(None, None).await;
+        } else if !can_refresh && now >= expiry.expires {
+            log::debug!("Token expired");
+            self.update_state(
+                OAuth2Context::NotAuthenticated {
+                    reason: Reason::Expired,
+                },
+                None,
+            );
+        } else {
+            self.schedule_check(can_refresh);
+        }
+    }
 
-            log::debug!("Login state: {state:?}");
+    /// Handle the browser going offline, or coming back online.
+    async fn connectivity(&mut self, online: bool) {
+        if !online {
+            log::info!("Offline, deferring token refresh");
+            self.events.emit(AuthEvent::Offline);
+            return;
+        }
 
-            let redirect_url = get_from_store(STORAGE_KEY_REDIRECT_URL)?;
-            log::debug!("Redirect URL: {redirect_url}");
-            let redirect_url = Url::parse(&redirect_url).map_err(|err| {
-                OAuth2Error::LoginResult(format!("Failed to parse redirect URL: {err}"))
-            })?;
+        log::info!("Back online");
+        self.events.emit(AuthEvent::Online);
 
-            let client = client.clone().set_redirect_uri(redirect_url);
+        let Some(expiry) = self.expiry else {
+            return;
+        };
 
-            let result = client.exchange_code(code, state).await;
-            self.update_state_from_result(result);
+        let now = Date::now() / 1000f64;
+        let deferred = now >= expiry.refresh_at || self.refresh_attempt > 0;
 
-            Ok(true)
+        if deferred
+            && !expiry.lazy
+            && self.refreshing.is_none()
+            && can_refresh(&self.state, self.config.as_ref())
+        {
+            // don't wait for a pending retry
+            self.timeout = None;
+            log::debug!("Refreshing token deferred while offline");
+            self.refresh(None, None).await;
         } else {
-            log::debug!("Neither an error nor a code. Continue without applying state.");
-            Ok(false)
+            self.check_expiry().await;
         }
     }
 
-    fn post_login_redirect(&self) -> Result<(), OAuth2Error> {
-        let config = self.config.as_ref().ok_or(OAuth2Error::NotInitialized)?;
-        let Some(redirect_callback) = config
-            .default_login_options
+    /// Reply with the current authentication, refreshing the token first if it is due.
+    async fn access_token(&mut self, reply: Reply<Authentication>) {
+        let auth = match &self.state {
+            OAuth2Context::Authenticated(auth) => auth.clone(),
+            OAuth2Context::NotInitialized => {
+                respond(reply, Err(OAuth2Error::not_initialized()));
+                return;
+            }
+            OAuth2Context::NotAuthenticated { .. } => {
+                respond(reply, Err(OAuth2Error::new(ErrorKind::NotAuthenticated)));
+                return;
+            }
+            OAuth2Context::Failed(err) => {
+                respond(reply, Err(err.clone()));
+                return;
+            }
+        };
+
+        let now = Date::now() / 1000f64;
+        let due = self.expiry.is_some_and(|expiry| now >= expiry.refresh_at);
+        let expired = self.expiry.is_some_and(|expiry| now >= expiry.expires);
+
+        // an expired token isn't handed out, failing the refresh while offline
+        if (expired || due && is_online()) && can_refresh(&self.state, self.config.as_ref()) {
+            log::debug!("Token requested, refreshing");
+            self.refresh(None, reply).await;
+        } else {
+            respond(reply, Ok(auth));
+        }
+    }
+
+    /// Evaluate the event of a transition from one state to another.
+    fn transition_event(current: &OAuth2Context, next: &OAuth2Context) -> Option<AuthEvent> {
+        match (current, next) {
+            (OAuth2Context::Authenticated(current), OAuth2Context::Authenticated(next)) => {
+                (current != next).then(|| AuthEvent::TokenRefreshed(next.clone()))
+            }
+            (_, OAuth2Context::Authenticated(next)) => Some(AuthEvent::LoggedIn(next.clone())),
+            (
+                OAuth2Context::NotAuthenticated {
+                    reason: Reason::Logout,
+                },
+                _,
+            ) => None,
+            (
+                _,
+                OAuth2Context::NotAuthenticated {
+                    reason: Reason::Logout,
+                },
+            ) => Some(AuthEvent::LoggedOut),
+            (
+                OAuth2Context::Authenticated(_),
+                OAuth2Context::NotAuthenticated {
+                    reason: Reason::Expired,
+                },
+            ) => Some(AuthEvent::SessionExpired),
+            _ => None,
+        }
+    }
+
+    /// Inform other tabs about a state change, if enabled.
+    fn broadcast_state(&self, state: &OAuth2Context, session_state: Option<&C::SessionState>) {
+        let Some(sync) = &self.sync else {
+            return;
+        };
+
+        let msg = match (state, shared_session(state, session_state)) {
+            (_, Some((authentication, session_state))) => match &self.state {
+                OAuth2Context::Authenticated(current) if *current == authentication => return,
+                OAuth2Context::Authenticated(_) => SyncMessage::Refreshed {
+                    authentication,
+                    session_state,
+                },
+                _ => SyncMessage::Login {
+                    authentication,
+                    session_state,
+                },
+            },
+            (
+                OAuth2Context::NotAuthenticated {
+                    reason: Reason::Logout | Reason::IdleTimeout | Reason::SessionLimit,
+                },
+                _,
+            ) => SyncMessage::Logout,
+            _ => return,
+        };
+
+        sync.send(&msg);
+    }
+
+    /// Apply a state change received from another tab.
+    fn apply_sync(&mut self, msg: SyncMessage<C::SessionState>) {
+        if self.client.is_none() {
+            log::debug!("Ignoring sync message, not yet initialized");
+            return;
+        }
+
+        log::debug!("Applying state from other tab");
+
+        match msg {
+            SyncMessage::Login {
+                authentication,
+                session_state,
+            }
+            | SyncMessage::Refreshed {
+                authentication,
+                session_state,
+            } => {
+                if self.client.as_ref().and_then(Client::dpop).is_some() {
+                    // the tokens of another tab can't be bound to the key of this tab
+                    log::debug!("Ignoring session of other tab, using DPoP");
+                    return;
+                }
+                self.set_state(
+                    OAuth2Context::Authenticated(authentication),
+                    Some(session_state),
+                );
+            }
+            SyncMessage::Activity { at } => {
+                if let Some(activity) = &self.activity {
+                    activity.record(at);
+                }
+                if self.session_warned {
+                    self.check_session();
+                }
+            }
+            SyncMessage::Logout => {
+                if matches!(self.state, OAuth2Context::Authenticated(_)) {
+                    self.set_state(
+                        OAuth2Context::NotAuthenticated {
+                            reason: Reason::Logout,
+                        },
+                        None,
+                    );
+                }
+            }
+            SyncMessage::Unknown => {
+                log::debug!("Ignoring unknown sync message");
+            }
+        }
+    }
+
+    /// Join the channel for synchronizing tabs, if enabled.
+    fn setup_sync(&mut self) {
+        self.sync = None;
+
+        let (Some(client), Some(config)) = (&self.client, &self.config) else {
+            return;
+        };
+        if !config.sync_tabs {
+            return;
+        }
+
+        let tx = self.tx.clone();
+        match TabSync::new(
+            config.namespace.as_deref(),
+            client.client_id(),
+            move |msg| {
+                let _ = tx.try_send(Msg::Sync(Box::new(msg)));
+            },
+        ) {
+            Ok(sync) => self.sync = Some(sync),
+            Err(err) => log::warn!("Failed to set up tab synchronization: {err}"),
+        }
+    }
+
+    /// Persist the session to the token store, if enabled.
+    fn persist_session(&self, state: &OAuth2Context, session_state: Option<&C::SessionState>) {
+        let Some(store) = self
+            .config
+            .as_ref()
+            .and_then(|config| config.token_store.clone())
+        else {
+            return;
+        };
+
+        let session = shared_session(state, session_state);
+
+        spawn_local(async move {
+            let result = match session {
+                Some(session) => save(&*store, STORAGE_KEY_SESSION, &session).await,
+                None => store.delete(STORAGE_KEY_SESSION).await,
+            };
+            if let Err(err) = result {
+                log::warn!("Failed to persist session: {err}");
+            }
+        });
+    }
+
+    /// Try to restore a previously persisted session.
+    ///
+    /// Returns `true` if a session was restored.
+    async fn restore_session(&mut self) -> bool {
+        let Some(store) = self
+            .config
+            .as_ref()
+            .and_then(|config| config.token_store.clone())
+        else {
+            return false;
+        };
+
+        if self.client.as_ref().and_then(Client::dpop).is_some() {
+            // the tokens of a previous page can't be bound to the key of this page
+            log::debug!("Not restoring persisted session, using DPoP");
+            return false;
+        }
+
+        let (auth, session_state) =
+            match load::<(Authentication, C::SessionState)>(&*store, STORAGE_KEY_SESSION).await {
+                Ok(Some(session)) => session,
+                Ok(None) => return false,
+                Err(err) => {
+                    log::warn!("Failed to load persisted session: {err}");
+                    return false;
+                }
+            };
+
+        let now = Date::now() / 1000f64;
+        let expired = auth.expires.is_some_and(|expires| (expires as f64) <= now);
+
+        if expired && auth.refresh_token.is_none() {
+            log::debug!("Persisted session expired");
+            if let Err(err) = store.delete(STORAGE_KEY_SESSION).await {
+                log::warn!("Failed to delete persisted session: {err}");
+            }
+            return false;
+        }
+
+        log::debug!("Restored persisted session");
+
+        // if the token already expired, this will trigger a refresh
+        self.update_state(OAuth2Context::Authenticated(auth), Some(session_state));
+
+        true
+    }
+
+    fn notify_state(&self, state: OAuth2Context) {
+        self.state_callback.emit(state);
+    }
+
+    /// Called once the configuration process has finished, applying the outcome.
+    async fn configured(
+        &mut self,
+        outcome: Result<(C, InnerConfig), OAuth2Error>,
+        change: ConfigChange,
+    ) -> Result<(), OAuth2Error> {
+        match outcome {
+            Ok((client, config)) => {
+                log::debug!("Client created");
+
+                self.client = Some(client);
+                self.config = Some(config);
+
+                self.setup_sync();
+                self.setup_activity();
+                self.events.emit(AuthEvent::Configured);
+
+                if !matches!(self.state, OAuth2Context::NotInitialized) {
+                    self.reconfigured(change).await;
+                } else {
+                    let detected = self.detect_state().await;
+                    log::debug!("Detected state: {detected:?}");
+                    match detected {
+                        Ok(true) => {
+                            if let Err(e) = self.post_login_redirect().await {
+                                error!("Post-login redirect failed: {e}");
+                            }
+                        }
+                        Ok(false) => {
+                            if !self.restore_session().await && !self.silent_login().await {
+                                self.update_state(
+                                    OAuth2Context::NotAuthenticated {
+                                        reason: Reason::NewSession,
+                                    },
+                                    None,
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            self.events.emit(AuthEvent::LoginFailed(err.clone()));
+                            self.update_state(err.into(), None);
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                log::debug!("Failed to configure client: {err}");
+                self.events
+                    .emit(AuthEvent::ConfigurationFailed(err.clone()));
+                // a session of a different issuer or client must not be kept
+                if matches!(self.state, OAuth2Context::NotInitialized)
+                    || change == ConfigChange::Identity
+                {
+                    self.update_state(err.clone().into(), None);
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply a configuration change to an already initialized agent.
+    async fn reconfigured(&mut self, change: ConfigChange) {
+        log::debug!("Reconfigured: {change:?}");
+
+        match change {
+            ConfigChange::Identity => {
+                // the session belongs to a different issuer or client, drop it and try to
+                // establish a new one
+                self.update_state(
+                    OAuth2Context::NotAuthenticated {
+                        reason: Reason::NewSession,
+                    },
+                    None,
+                );
+                if !self.silent_login().await {
+                    self.update_state(
+                        OAuth2Context::NotAuthenticated {
+                            reason: Reason::NewSession,
+                        },
+                        None,
+                    );
+                }
+            }
+            ConfigChange::ScopesGrown
+                if matches!(self.state, OAuth2Context::Authenticated(_))
+                    && self.config.as_ref().is_some_and(|config| config.reconsent) =>
+            {
+                log::info!("Requested scopes changed, starting a new login");
+                if let Err(err) = self.start_login(None).await {
+                    self.events.emit(AuthEvent::LoginFailed(err.clone()));
+                    self.update_state(err.into(), None);
+                }
+            }
+            ConfigChange::ScopesGrown | ConfigChange::Options => {
+                // keep the session, but apply the options (like timers, or storage)
+                let state = self.state.clone();
+                let session_state = self.session_state.clone();
+                self.update_expiry(&state);
+                self.schedule_check(can_refresh(&state, self.config.as_ref()));
+                self.persist_session(&state, session_state.as_ref());
+                self.check_session();
+            }
+        }
+    }
+
+    async fn make_client(
+        config: AgentConfiguration<C>,
+        client: Option<C>,
+    ) -> Result<(C, InnerConfig), OAuth2Error> {
+        let AgentConfiguration {
+            config,
+            namespace,
+            scopes,
+            grace_period,
+            refresh_policy,
+            audience,
+            default_login_options,
+            default_logout_options,
+            max_expiration,
+            silent_login,
+            state_storage,
+            token_storage,
+            sync_tabs,
+            refresh_retry,
+            reconsent,
+            idle_timeout,
+            session_limit,
+            session_warning,
+        } = config;
+
+        let client = match client {
+            Some(client) => client,
+            None => C::from_config(config).await?,
+        };
+
+        let state_store = state_storage.open_namespaced(namespace.as_deref()).await;
+        let token_store = match token_storage {
+            Some(token_storage) => Some(token_storage.open_namespaced(namespace.as_deref()).await),
+            None => None,
+        };
+
+        let inner = InnerConfig {
+            namespace,
+            scopes,
+            refresh_policy: refresh_policy.unwrap_or_else(|| Rc::new(GracePeriod(grace_period))),
+            audience,
+            default_login_options,
+            default_logout_options,
+            max_expiration,
+            silent_login,
+            state_store,
+            token_store,
+            sync_tabs,
+            refresh_retry,
+            reconsent,
+            max_age: None,
+            acr_values: vec![],
+            response_mode: None,
+            authorization_details: vec![],
+            idle_timeout,
+            session_limit,
+            session_warning,
+        };
+
+        Ok((client, inner))
+    }
+
+    /// When initializing, try to detect the state from the URL and session state.
+    ///
+    /// Returns `false` if there is no authentication state found and the result is final.
+    /// Otherwise, it returns `true` and spawns a request for e.g. a code exchange.
+    async fn detect_state(&mut self) -> Result<bool, OAuth2Error> {
+        if self.client.is_none() {
+            return Err(OAuth2Error::not_initialized());
+        }
+
+        let Some(state) = self.response.take() else {
+            log::debug!("No authorization response. Continue without applying state.");
+            return Ok(false);
+        };
+
+        log::debug!("Found state: {state:?}",);
+
+        if let Some(error) = state.error() {
+            log::info!("Login error from server: {error}");
+
+            // error from the OAuth2 server
+            return Err(error);
+        }
+
+        let store = self
+            .config
+            .as_ref()
+            .map(|config| config.state_store.clone())
+            .ok_or_else(OAuth2Error::not_initialized)?;
+
+        let csrf_token = get_from_store(&*store, STORAGE_KEY_CSRF_TOKEN).await?;
+
+        let login_state: C::LoginState = load(&*store, STORAGE_KEY_LOGIN_STATE)
+            .await
+            .map_err(|err| OAuth2Error::storage(format!("Failed to load login state: {err}")))?
+            .ok_or_else(|| OAuth2Error::storage_key_empty(STORAGE_KEY_LOGIN_STATE))?;
+
+        log::debug!("Login state: {login_state:?}");
+
+        let redirect_url = get_from_store(&*store, STORAGE_KEY_REDIRECT_URL).await?;
+        log::debug!("Redirect URL: {redirect_url}");
+        let redirect_url = Url::parse(&redirect_url).map_err(|err| {
+            OAuth2Error::login_result(format!("Failed to parse redirect URL: {err}"))
+        })?;
+
+        let scopes = match load::<Vec<String>>(&*store, STORAGE_KEY_SCOPES).await {
+            Ok(Some(scopes)) => scopes,
+            // started by a previous version
+            _ => self
+                .config
+                .as_ref()
+                .map(|config| config.scopes.clone())
+                .unwrap_or_default(),
+        };
+
+        let transaction = LoginTransaction {
+            csrf_token,
+            login_state,
+            redirect_url,
+            scopes,
+        };
+
+        let result = self.complete_login(state, transaction).await;
+        if let Err(err) = &result {
+            self.events.emit(AuthEvent::LoginFailed(err.clone()));
+        }
+        self.update_state_from_result(result);
+
+        Ok(true)
+    }
+
+    /// Complete a login, by validating the authorization response and exchanging the code.
+    ///
+    /// If the issuer doesn't report the granted scopes, the requested scopes are granted.
+    async fn complete_login(
+        &mut self,
+        state: State,
+        transaction: LoginTransaction<C>,
+    ) -> Result<(OAuth2Context, C::SessionState), OAuth2Error> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+
+        let code = Self::authorization_code(state, &transaction.csrf_token)?;
+
+        let (mut state, session_state) = client
+            .clone()
+            .set_redirect_uri(transaction.redirect_url)
+            .exchange_code(code, transaction.login_state)
+            .await?;
+
+        if let OAuth2Context::Authenticated(auth) = &mut state {
+            let granted = auth.scopes.get_or_insert(transaction.scopes.clone());
+            self.denied_scopes = transaction
+                .scopes
+                .into_iter()
+                .filter(|scope| !granted.contains(scope))
+                .collect();
+        }
+
+        Ok((state, session_state))
+    }
+
+    /// Validate the authorization response against the expected state, and extract the code.
+    fn authorization_code(state: State, expected_state: &str) -> Result<String, OAuth2Error> {
+        if let Some(error) = state.error() {
+            return Err(error);
+        }
+
+        let code = state
+            .code
+            .ok_or_else(|| OAuth2Error::login_result("Missing code from server".to_string()))?;
+
+        match state.state {
+            None => Err(OAuth2Error::login_result(
+                "Missing state from server".to_string(),
+            )),
+            Some(state) if state != expected_state => {
+                Err(OAuth2Error::new(ErrorKind::StateMismatch).with_description("State mismatch"))
+            }
+            Some(_) => Ok(code),
+        }
+    }
+
+    /// Try to (re-)authenticate using a `prompt=none` request in a hidden iframe.
+    ///
+    /// Returns `true` if the session was authenticated, `false` if silent login is not configured,
+    /// or the user needs to log in interactively.
+    async fn silent_login(&mut self) -> bool {
+        match self.try_silent_login().await {
+            Ok(result) => result,
+            Err(err) => {
+                log::warn!("Silent login failed: {err}");
+                false
+            }
+        }
+    }
+
+    async fn try_silent_login(&mut self) -> Result<bool, OAuth2Error> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+
+        let Some(options) = config.silent_login.clone() else {
+            return Ok(false);
+        };
+
+        log::debug!("Trying silent login");
+
+        // a max_age would add prompt=login, which can't be combined with prompt=none
+        let mut config = config.for_login(config.default_login_options.as_ref());
+        config.max_age = None;
+        let login_context = client.make_login_context(&config, options.redirect_url.clone())?;
+
+        let hint = options
+            .id_token_hint
+            .then(|| {
+                self.session_state
+                    .as_ref()
+                    .and_then(|session_state| client.id_token_hint(session_state))
+            })
+            .flatten();
+        let login_url = silent::silent_url(
+            login_context.url,
+            config
+                .default_login_options
+                .iter()
+                .flat_map(|opts| opts.query.iter()),
+            hint.as_deref(),
+        );
+
+        let login_url = client.authorization_url(login_url).await?;
+
+        let response = silent::authorize(&login_url, options.timeout).await?;
+        let state = State::from_url(&response);
+
+        if let Some(error) = state
+            .error()
+            .filter(|error| error.kind == ErrorKind::InteractionRequired)
+        {
+            log::debug!("Silent login requires interaction: {error}");
+            return Ok(false);
+        }
+
+        let transaction = LoginTransaction {
+            csrf_token: login_context.csrf_token,
+            login_state: login_context.state,
+            redirect_url: options.redirect_url,
+            scopes: config.scopes,
+        };
+
+        let (state, session_state) = self.complete_login(state, transaction).await?;
+
+        self.update_state(state, Some(session_state));
+
+        Ok(true)
+    }
+
+    async fn post_login_redirect(&self) -> Result<(), OAuth2Error> {
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+        let Some(redirect_callback) = config
+            .default_login_options
             .as_ref()
             .and_then(|opts| opts.post_login_redirect_callback.clone())
         else {
             return Ok(());
         };
-        let Some(url) = get_from_store_optional(STORAGE_KEY_POST_LOGIN_URL)? else {
+        let store = &*config.state_store;
+        let Some(url) = get_from_store_optional(store, STORAGE_KEY_POST_LOGIN_URL).await? else {
             return Ok(());
         };
-        SessionStorage::delete(STORAGE_KEY_POST_LOGIN_URL);
+        store.delete(STORAGE_KEY_POST_LOGIN_URL).await?;
         redirect_callback.emit(url);
 
         Ok(())
@@ -491,59 +1683,327 @@ where
             Ok((state, session_state)) => {
                 self.update_state(state, Some(session_state));
             }
-            Err(err) => {
-                self.update_state(err.into(), None);
+            Err(err) => {
+                self.update_state(err.into(), None);
+            }
+        }
+    }
+
+    async fn refresh(&mut self, options: Option<RefreshOptions>, reply: Reply<Authentication>) {
+        if let Some(refreshing) = &mut self.refreshing {
+            if refreshing.options == options {
+                log::debug!("Joining refresh in progress");
+                refreshing.waiters.extend(reply);
+            } else {
+                refreshing.deferred.push((options, reply));
+            }
+            return;
+        }
+
+        if !is_online() {
+            if reply.is_some() {
+                respond(
+                    reply,
+                    Err(OAuth2Error::new(ErrorKind::Network).with_description("offline")),
+                );
+            } else {
+                // performed once the browser is online again
+                log::debug!("Offline, deferring refresh");
+                self.defer_refresh();
+            }
+            return;
+        }
+
+        let (client, session_state) =
+            if let (Some(client), Some(session_state)) = (&self.client, &self.session_state) {
+                (client.clone(), session_state.clone())
+            } else {
+                if reply.is_some() {
+                    // an explicit request, don't touch the current state
+                    respond(
+                        reply,
+                        Err(OAuth2Error::refresh("not authenticated".to_string())),
+                    );
+                    return;
+                }
+                // we need to refresh but lost our client
+                self.update_state(
+                    OAuth2Context::NotAuthenticated {
+                        reason: Reason::Expired,
+                    },
+                    None,
+                );
+                return;
+            };
+
+        if let OAuth2Context::Authenticated(Authentication {
+            refresh_token: Some(refresh_token),
+            ..
+        }) = &self.state
+        {
+            log::debug!("Triggering refresh");
+
+            let refresh_token = refresh_token.clone();
+            self.refreshing = Some(Refreshing {
+                refresh_token: refresh_token.clone(),
+                options: options.clone(),
+                waiters: reply.into_iter().collect(),
+                deferred: vec![],
+            });
+
+            // don't block the agent while waiting for the issuer
+            let tx = self.tx.clone();
+            self.tasks.spawn(async move {
+                let result = client
+                    .exchange_refresh_token(
+                        refresh_token,
+                        session_state,
+                        options.unwrap_or_default(),
+                    )
+                    .await;
+                let _ = tx.send(Msg::RefreshResult(Box::new(result))).await;
+            });
+        } else if self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.silent_login.is_some())
+        {
+            // no refresh token, try renewing the session
+            log::debug!("Triggering silent renew");
+
+            if self.silent_login().await {
+                let result = self.state.authentication().cloned().ok_or_else(|| {
+                    OAuth2Error::refresh("silent renew returned no authentication".to_string())
+                });
+                respond(reply, result);
+            } else {
+                self.update_state(
+                    OAuth2Context::NotAuthenticated {
+                        reason: Reason::Expired,
+                    },
+                    None,
+                );
+                respond(
+                    reply,
+                    Err(OAuth2Error::refresh(
+                        "failed to renew the session".to_string(),
+                    )),
+                );
+            }
+        } else {
+            respond(
+                reply,
+                Err(OAuth2Error::refresh(
+                    "no refresh token available".to_string(),
+                )),
+            );
+        }
+    }
+
+    /// Apply the outcome of a refresh, and report it to all callers waiting for it.
+    fn refresh_result(&mut self, result: Result<(OAuth2Context, C::SessionState), OAuth2Error>) {
+        let Some(refreshing) = self.refreshing.take() else {
+            return;
+        };
+
+        let result = match &self.state {
+            OAuth2Context::Authenticated(Authentication {
+                refresh_token: Some(refresh_token),
+                expires,
+                scopes,
+                authorization_details,
+                ..
+            }) if *refresh_token == refreshing.refresh_token => {
+                let expires = *expires;
+                match result {
+                    Ok((mut state, session_state)) => {
+                        if let OAuth2Context::Authenticated(auth) = &mut state {
+                            if auth.scopes.is_none() {
+                                // without scopes, the issuer grants the scopes of the original request
+                                auth.scopes = match refreshing.options.as_ref() {
+                                    Some(options) if !options.scopes.is_empty() => {
+                                        Some(options.scopes.clone())
+                                    }
+                                    _ => scopes.clone(),
+                                };
+                            }
+                            if auth.authorization_details.is_none() {
+                                // the same applies to the authorization details
+                                auth.authorization_details = match refreshing.options.as_ref() {
+                                    Some(options) if !options.authorization_details.is_empty() => {
+                                        Some(options.authorization_details.clone())
+                                    }
+                                    _ => authorization_details.clone(),
+                                };
+                            }
+                        }
+                        let result = state.authentication().cloned().ok_or_else(|| {
+                            OAuth2Error::refresh("refresh returned no authentication".to_string())
+                        });
+                        self.update_state(state, Some(session_state));
+                        result
+                    }
+                    Err(err) if err.is_temporary() => {
+                        log::warn!("Failed to refresh token, temporary error: {err}");
+                        self.retry_refresh(expires, err.clone());
+                        Err(err)
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to refresh token: {err}");
+                        self.events.emit(AuthEvent::RefreshFailed {
+                            error: err.clone(),
+                            retry: None,
+                        });
+                        self.update_state(err.clone().into(), None);
+                        Err(err)
+                    }
+                }
+            }
+            // the session changed in the meantime (e.g. by another tab), discard the outcome
+            OAuth2Context::Authenticated(auth) => {
+                log::debug!("Session changed during refresh, discarding result");
+                Ok(auth.clone())
+            }
+            _ => {
+                log::debug!("Session ended during refresh, discarding result");
+                Err(OAuth2Error::refresh(
+                    "session ended during refresh".to_string(),
+                ))
+            }
+        };
+
+        for waiter in refreshing.waiters {
+            let _ = waiter.send(result.clone());
+        }
+
+        for (options, reply) in refreshing.deferred {
+            if let Err(err) = self.tx.try_send(Msg::Refresh(options, reply)) {
+                log::warn!("Failed to schedule deferred refresh: {err}");
             }
         }
     }
 
-    async fn refresh(&mut self) {
-        let (client, session_state) =
-            if let (Some(client), Some(session_state)) = (&self.client, &self.session_state) {
-                (client.clone(), session_state.clone())
-            } else {
-                // we need to refresh but lost our client
+    /// Keep checking the session, while refreshing the token is deferred as the browser is
+    /// offline.
+    ///
+    /// Once the access token expired, this is reported by [`AuthEvent::TokenExpired`]. Once the
+    /// refresh token expired too, the session is expired.
+    fn defer_refresh(&mut self) {
+        self.timeout = None;
+
+        let Some(expiry) = self.expiry else {
+            return;
+        };
+        let refresh_expires = self
+            .state
+            .authentication()
+            .and_then(|auth| auth.refresh_expires)
+            .map(|refresh_expires| refresh_expires as f64);
+
+        let now = Date::now() / 1000f64;
+        match Deferred::evaluate(now, expiry.expires, refresh_expires) {
+            Deferred::RefreshExpired => {
+                log::info!("Refresh token expired while offline");
                 self.update_state(
                     OAuth2Context::NotAuthenticated {
                         reason: Reason::Expired,
                     },
                     None,
                 );
-                return;
-            };
-
-        if let OAuth2Context::Authenticated(Authentication {
-            refresh_token: Some(refresh_token),
-            ..
-        }) = &self.state
-        {
-            log::debug!("Triggering refresh");
+            }
+            Deferred::Pending {
+                token_expired,
+                next,
+            } => {
+                if token_expired && !self.token_expired {
+                    log::info!("Access token expired while offline");
+                    self.token_expired = true;
+                    self.events.emit(AuthEvent::TokenExpired);
+                }
+                if let Some(next) = next {
+                    let tx = self.tx.clone();
+                    self.timeout =
+                        Some(chunked_timeout(duration_from_secs(next - now), move || {
+                            let _ = tx.try_send(Msg::CheckExpiry);
+                        }));
+                }
+            }
+        }
+    }
 
-            let result = client
-                .exchange_refresh_token(refresh_token.clone(), session_state)
-                .await;
+    /// Schedule a retry of a failed refresh, as long as the token is still valid.
+    fn retry_refresh(&mut self, expires: Option<u64>, err: OAuth2Error) {
+        if !is_online() {
+            log::info!("Offline, deferring refresh retry");
+            self.defer_refresh();
+            return;
+        }
 
-            if let Err(err) = &result {
-                log::warn!("Failed to refresh token: {err}");
-            }
+        let delay = self
+            .config
+            .as_ref()
+            .and_then(|config| config.refresh_retry.delay(self.refresh_attempt));
+
+        let Some(delay) = delay else {
+            log::info!("Giving up refreshing the token");
+            self.events.emit(AuthEvent::RefreshFailed {
+                error: err.clone(),
+                retry: None,
+            });
+            self.update_state(err.into(), None);
+            return;
+        };
 
-            self.update_state_from_result(result);
+        let now = Date::now() / 1000f64;
+        if expires.is_some_and(|expires| expires as f64 <= now + delay.as_secs_f64()) {
+            log::info!("Token expires before the refresh can be retried");
+            self.events.emit(AuthEvent::RefreshFailed {
+                error: err,
+                retry: None,
+            });
+            self.update_state(
+                OAuth2Context::NotAuthenticated {
+                    reason: Reason::Expired,
+                },
+                None,
+            );
+            return;
         }
-    }
 
-    /// Extract the state from the query.
-    fn find_query_state() -> Option<State> {
-        if let Ok(url) = Self::current_url() {
-            let query: HashMap<_, _> = url.query_pairs().collect();
+        self.refresh_attempt += 1;
+        self.events.emit(AuthEvent::RefreshFailed {
+            error: err,
+            retry: Some(delay),
+        });
+        log::debug!(
+            "Retrying refresh in {}ms (attempt {})",
+            delay.as_millis(),
+            self.refresh_attempt
+        );
+
+        let tx = self.tx.clone();
+        let millis = delay.as_millis().to_u32().unwrap_or(i32::MAX as u32);
+        self.timeout = Some(Timeout::new(millis, move || {
+            let _ = tx.try_send(Msg::Refresh(None, None));
+        }));
+    }
 
-            Some(State {
-                code: query.get("code").map(ToString::to_string),
-                state: query.get("state").map(ToString::to_string),
-                error: query.get("error").map(ToString::to_string),
-            })
-        } else {
-            None
+    /// Take the authorization response of the namespace from the current URL.
+    ///
+    /// The response is removed from the URL right away, before the application gets rendered,
+    /// so that the code can't leak (e.g. through the `Referer` header or analytics).
+    fn take_response(namespace: Option<&str>) -> Option<State> {
+        let state = State::from_url(&Self::current_url().ok()?);
+        if state.code.is_none() && state.error.is_none() {
+            return None;
+        }
+        if !state.is_for(namespace) {
+            log::debug!("Response belongs to a different agent, ignoring");
+            return None;
         }
+
+        Self::cleanup_url(state.location);
+        Some(state)
     }
 
     fn current_url() -> Result<Url, String> {
@@ -554,9 +2014,10 @@ where
         Url::parse(&href).map_err(|err| err.to_string())
     }
 
-    fn cleanup_url() {
+    /// Remove the authorization response from the current URL.
+    fn cleanup_url(location: ResponseLocation) {
         if let Ok(mut url) = Self::current_url() {
-            url.set_query(None);
+            location.remove(&mut url);
             let state = history().state().unwrap_or(JsValue::NULL);
             history()
                 .replace_state_with_url(&state, "", Some(url.as_str()))
@@ -564,18 +2025,108 @@ where
         }
     }
 
-    async fn configure(&mut self, config: AgentConfiguration<C>) {
-        self.configured(Self::make_client(config).await).await;
+    async fn configure(
+        &mut self,
+        config: AgentConfiguration<C>,
+        response: Option<State>,
+    ) -> Result<(), OAuth2Error> {
+        if response.is_some() {
+            self.response = response;
+        }
+
+        let change = match &self.configuration {
+            Some(previous) => ConfigChange::evaluate(previous, &config),
+            None => ConfigChange::Identity,
+        };
+
+        // re-use the client, unless its configuration changed
+        let client = match change {
+            ConfigChange::Identity => None,
+            ConfigChange::ScopesGrown | ConfigChange::Options => self.client.clone(),
+        };
+
+        self.configuration = Some(config.clone());
+        let outcome = Self::make_client(config, client).await;
+        self.configured(outcome, change).await
+    }
+
+    /// Reset the agent, e.g. to recover from a failure.
+    ///
+    /// This drops the current session, clears any pending login transaction, and re-creates the
+    /// client from the most recent configuration.
+    async fn reset(&mut self) -> Result<(), OAuth2Error> {
+        log::debug!("Resetting agent");
+
+        let configuration = self
+            .configuration
+            .clone()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+
+        if let Some(refreshing) = self.refreshing.take() {
+            let err = OAuth2Error::refresh("agent was reset");
+            for waiter in refreshing.waiters {
+                let _ = waiter.send(Err(err.clone()));
+            }
+            for (_, reply) in refreshing.deferred {
+                respond(reply, Err(err.clone()));
+            }
+        }
+
+        if let Some(config) = &self.config {
+            Self::clear_transaction(&*config.state_store).await;
+        }
+
+        match Self::make_client(configuration, None).await {
+            Ok((client, config)) => {
+                self.client = Some(client);
+                self.config = Some(config);
+
+                self.setup_sync();
+                self.setup_activity();
+                self.events.emit(AuthEvent::Configured);
+
+                self.update_state(
+                    OAuth2Context::NotAuthenticated {
+                        reason: Reason::NewSession,
+                    },
+                    None,
+                );
+                Ok(())
+            }
+            Err(err) => {
+                log::debug!("Failed to configure client: {err}");
+                self.events
+                    .emit(AuthEvent::ConfigurationFailed(err.clone()));
+                self.update_state(err.clone().into(), None);
+                Err(err)
+            }
+        }
+    }
+
+    /// Remove the transient state of a login flow from the store.
+    async fn clear_transaction(store: &dyn StateStore) {
+        for key in STORAGE_KEYS_TRANSACTION {
+            if let Err(err) = store.delete(key).await {
+                log::warn!("Failed to delete {key}: {err}");
+            }
+        }
     }
 
-    fn start_login(&mut self, options: Option<LoginOptions>) -> Result<(), OAuth2Error> {
-        let client = self.client.as_ref().ok_or(OAuth2Error::NotInitialized)?;
-        let config = self.config.as_ref().ok_or(OAuth2Error::NotInitialized)?;
+    async fn start_login(&mut self, options: Option<LoginOptions>) -> Result<(), OAuth2Error> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
 
         let options =
             options.unwrap_or_else(|| config.default_login_options.clone().unwrap_or_default());
+        let login_config = config.for_login(Some(&options));
 
-        let current_url = Self::current_url().map_err(OAuth2Error::StartLogin)?;
+        let current_url = Self::current_url().map_err(OAuth2Error::start_login)?;
 
         // take the parameter value first, then the agent configured value, then fall back to the default
         let redirect_url = options
@@ -588,33 +2139,47 @@ where
             })
             .unwrap_or_else(|| current_url.clone());
 
+        let store = &*config.state_store;
+
         if redirect_url != current_url {
-            SessionStorage::set(STORAGE_KEY_POST_LOGIN_URL, current_url)
-                .map_err(|err| OAuth2Error::StartLogin(err.to_string()))?;
+            save(store, STORAGE_KEY_POST_LOGIN_URL, &current_url)
+                .await
+                .map_err(|err| OAuth2Error::start_login(err.to_string()))?;
         }
 
-        let login_context = client.make_login_context(config, redirect_url.clone())?;
+        let login_context = client.make_login_context(&login_config, redirect_url.clone())?;
+
+        save(store, STORAGE_KEY_CSRF_TOKEN, &login_context.csrf_token)
+            .await
+            .map_err(|err| OAuth2Error::start_login(err.to_string()))?;
 
-        SessionStorage::set(STORAGE_KEY_CSRF_TOKEN, login_context.csrf_token)
-            .map_err(|err| OAuth2Error::StartLogin(err.to_string()))?;
+        save(store, STORAGE_KEY_SCOPES, &login_config.scopes)
+            .await
+            .map_err(|err| OAuth2Error::start_login(err.to_string()))?;
 
-        SessionStorage::set(STORAGE_KEY_LOGIN_STATE, login_context.state)
-            .map_err(|err| OAuth2Error::StartLogin(err.to_string()))?;
+        save(store, STORAGE_KEY_LOGIN_STATE, &login_context.state)
+            .await
+            .map_err(|err| OAuth2Error::start_login(err.to_string()))?;
 
-        SessionStorage::set(STORAGE_KEY_REDIRECT_URL, redirect_url)
-            .map_err(|err| OAuth2Error::StartLogin(err.to_string()))?;
+        save(store, STORAGE_KEY_REDIRECT_URL, &redirect_url)
+            .await
+            .map_err(|err| OAuth2Error::start_login(err.to_string()))?;
 
         let mut login_url = login_context.url;
 
         login_url.query_pairs_mut().extend_pairs(options.query);
 
+        let login_url = client.authorization_url(login_url).await?;
+
+        self.events.emit(AuthEvent::LoginStarted);
+
         // the next call will most likely navigate away from this page
 
         window()
             .location()
             .set_href(login_url.as_str())
             .map_err(|err| {
-                OAuth2Error::StartLogin(
+                OAuth2Error::start_login(
                     err.as_string()
                         .unwrap_or_else(|| "Unable to navigate to login page".to_string()),
                 )
@@ -623,7 +2188,246 @@ where
         Ok(())
     }
 
-    fn logout_opts(&mut self, options: Option<LogoutOptions>) {
+    /// Start the redirect login, replying once the browser navigates to the issuer, or with the
+    /// error if the login couldn't be started.
+    async fn login(&mut self, options: Option<LoginOptions>, reply: Reply<()>) {
+        let result = self.start_login(options).await;
+        if let Err(err) = &result {
+            log::info!("Failed to start login: {err}");
+            self.events.emit(AuthEvent::LoginFailed(err.clone()));
+        }
+        respond(reply, result);
+    }
+
+    /// Start the popup login, replying once the popup reported back, or with the error if the
+    /// login couldn't be started.
+    fn login_popup(
+        &mut self,
+        options: LoginOptions,
+        popup: PopupWindow,
+        reply: Reply<Authentication>,
+    ) {
+        // on success, the reply is sent once the popup reported back
+        if let Err((err, reply)) = self.start_login_popup(options, popup, reply) {
+            log::info!("Failed to start popup login: {err}");
+            self.events.emit(AuthEvent::LoginFailed(err.clone()));
+            respond(reply, Err(err));
+        }
+    }
+
+    /// The current authentication, or an error if not authenticated.
+    fn authentication(&self) -> Result<Authentication, OAuth2Error> {
+        match &self.state {
+            OAuth2Context::Authenticated(auth) => Ok(auth.clone()),
+            OAuth2Context::NotInitialized => Err(OAuth2Error::not_initialized()),
+            OAuth2Context::NotAuthenticated { .. } => {
+                Err(OAuth2Error::new(ErrorKind::NotAuthenticated))
+            }
+            OAuth2Context::Failed(err) => Err(err.clone()),
+        }
+    }
+
+    /// Evaluate the options for requesting additional scopes and authorization details, keeping
+    /// the scopes and details granted to the current session.
+    ///
+    /// Returns [`None`] if all scopes and details are granted already.
+    fn step_up(
+        &self,
+        scopes: Vec<String>,
+        authorization_details: Vec<AuthorizationDetail>,
+    ) -> Result<Option<LoginOptions>, OAuth2Error> {
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+
+        let auth = self.state.authentication();
+        let granted = auth
+            .and_then(|auth| auth.scopes.clone())
+            .unwrap_or_default();
+        let missing: Vec<_> = scopes
+            .into_iter()
+            .filter(|scope| !granted.contains(scope))
+            .collect();
+        let granted_details = auth
+            .and_then(|auth| auth.authorization_details.clone())
+            .unwrap_or_default();
+        let missing_details: Vec<_> = authorization_details
+            .into_iter()
+            .filter(|detail| !granted_details.contains(detail))
+            .collect();
+
+        if missing.is_empty() && missing_details.is_empty() {
+            return Ok(None);
+        }
+        if missing_details.is_empty()
+            && missing
+                .iter()
+                .all(|scope| self.denied_scopes.contains(scope))
+        {
+            // don't ask for the same scopes over and over again
+            return Err(OAuth2Error::new(ErrorKind::AccessDenied)
+                .with_description(format!("scopes were not granted: {}", missing.join(" "))));
+        }
+
+        let mut scopes = config.scopes.clone();
+        for scope in granted.into_iter().chain(missing) {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+
+        let mut options = config.default_login_options.clone().unwrap_or_default();
+        let mut authorization_details = options.authorization_details.clone();
+        for detail in granted_details.into_iter().chain(missing_details) {
+            if !authorization_details.contains(&detail) {
+                authorization_details.push(detail);
+            }
+        }
+
+        log::info!(
+            "Requesting additional scopes: {scopes:?}, authorization details: {authorization_details:?}"
+        );
+
+        options = options
+            .with_scopes(scopes)
+            .with_authorization_details(authorization_details);
+        Ok(Some(options))
+    }
+
+    fn start_login_popup(
+        &mut self,
+        options: LoginOptions,
+        popup: PopupWindow,
+        reply: Reply<Authentication>,
+    ) -> Result<(), (OAuth2Error, Reply<Authentication>)> {
+        let popup = match popup {
+            Ok(popup) => popup,
+            Err(err) => return Err((err, reply)),
+        };
+
+        match self.prepare_login_popup(options) {
+            Ok((client, login_url, transaction)) => {
+                self.events.emit(AuthEvent::LoginStarted);
+
+                let tx = self.tx.clone();
+                self.tasks.spawn(async move {
+                    let response = match client.authorization_url(login_url).await {
+                        Ok(login_url) => callback::navigate(&popup, &login_url),
+                        Err(err) => Err(err),
+                    };
+                    let response = match response {
+                        Ok(()) => callback::receive(popup.clone(), None).await,
+                        Err(err) => Err(err),
+                    };
+                    popup.close().ok();
+                    let _ = tx
+                        .send(Msg::PopupCallback(Box::new(PopupCallback {
+                            response,
+                            transaction,
+                            reply,
+                        })))
+                        .await;
+                });
+                Ok(())
+            }
+            Err(err) => {
+                popup.close().ok();
+                Err((err, reply))
+            }
+        }
+    }
+
+    /// Prepare a login in the popup.
+    ///
+    /// Returns the client for pushing the authorization request (if used), the URL to navigate
+    /// the popup to, and the transaction for completing the login.
+    fn prepare_login_popup(
+        &self,
+        options: LoginOptions,
+    ) -> Result<(C, Url, LoginTransaction<C>), OAuth2Error> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?;
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(OAuth2Error::not_initialized)?
+            .for_login(Some(&options));
+
+        // the popup must be redirected to a callback page, not the application itself
+        let redirect_url = options
+            .redirect_url
+            .or_else(|| {
+                config
+                    .silent_login
+                    .as_ref()
+                    .map(|opts| opts.redirect_url.clone())
+            })
+            .ok_or_else(|| {
+                OAuth2Error::start_login("Popup login requires a callback redirect URL".to_string())
+            })?;
+
+        let login_context = client.make_login_context(&config, redirect_url.clone())?;
+
+        let mut login_url = login_context.url;
+        login_url.query_pairs_mut().extend_pairs(options.query);
+
+        let transaction = LoginTransaction {
+            csrf_token: login_context.csrf_token,
+            login_state: login_context.state,
+            redirect_url,
+            scopes: config.scopes,
+        };
+
+        Ok((client.clone(), login_url, transaction))
+    }
+
+    async fn popup_callback(&mut self, callback: PopupCallback<C>) {
+        let PopupCallback {
+            response,
+            transaction,
+            reply,
+        } = callback;
+
+        let result = match response {
+            Ok(url) => {
+                self.complete_login(State::from_url(&url), transaction)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match &result {
+            Ok(_) => {}
+            Err(err) if matches!(err.kind, ErrorKind::PopupBlocked | ErrorKind::PopupClosed) => {
+                // the user aborted, keep the current state
+                log::info!("Popup login aborted: {err}");
+                self.events.emit(AuthEvent::LoginFailed(err.clone()));
+            }
+            Err(err) if matches!(self.state, OAuth2Context::Authenticated(_)) => {
+                // e.g. a failed re-authentication or step-up, keep the current session
+                log::info!("Popup login failed: {err}");
+                self.events.emit(AuthEvent::LoginFailed(err.clone()));
+            }
+            Err(err) => {
+                self.events.emit(AuthEvent::LoginFailed(err.clone()));
+                self.update_state(err.clone().into(), None);
+            }
+        }
+
+        respond(
+            reply,
+            result.and_then(|(state, session_state)| {
+                self.update_state(state, Some(session_state));
+                self.authentication()
+            }),
+        );
+    }
+
+    /// End the session, for the provided reason.
+    fn logout_opts(&mut self, options: Option<LogoutOptions>, reason: Reason) {
         if let Some(client) = &self.client {
             if let Some(session_state) = self.session_state.clone() {
                 // let the client know that log out, clients may navigate to a different
@@ -643,12 +2447,67 @@ where
         // There is a bug in yew, which panics during re-rendering, which might be triggered
         // by the next step. Doing the update later, might not trigger the issue as it might
         // cause the application to navigate to a different page.
-        self.update_state(
-            OAuth2Context::NotAuthenticated {
-                reason: Reason::Logout,
-            },
-            None,
-        );
+        self.update_state(OAuth2Context::NotAuthenticated { reason }, None);
+    }
+}
+
+impl<C> Agent<C>
+where
+    C: Client,
+{
+    /// Create a message for the agent, returning a future sending it and waiting for the reply.
+    fn request<T: 'static>(
+        &self,
+        f: impl FnOnce(Reply<T>) -> Msg<C>,
+    ) -> LocalBoxFuture<'static, Result<T, OAuth2Error>> {
+        let (reply, rx) = oneshot::channel();
+        let msg = f(Some(reply));
+        let tx = self.tx.clone();
+
+        async move {
+            tx.send(msg).await.map_err(Error::from)?;
+            // the agent dropping the reply means it is gone
+            rx.await.map_err(|_| Error::NoAgent)?
+        }
+        .boxed_local()
+    }
+
+    /// Open a popup window, and hand it over to the agent with the message.
+    ///
+    /// Browsers only allow opening a popup in response to a user interaction, so this happens
+    /// right away, on the caller's side. The agent only navigates the popup to the login page.
+    fn send_popup(&self, f: impl FnOnce(PopupWindow) -> Msg<C>) -> Result<(), Error> {
+        let popup = callback::open_popup();
+        let window = popup.clone();
+        self.tx.try_send(f(popup)).map_err(|err| {
+            close_popup(window);
+            Error::from(err)
+        })
+    }
+
+    /// Open a popup window, and hand it over to the agent with the message, returning a future
+    /// waiting for the reply.
+    ///
+    /// See [`Self::send_popup`].
+    fn request_popup<T: 'static>(
+        &self,
+        f: impl FnOnce(PopupWindow, Reply<T>) -> Msg<C>,
+    ) -> LocalBoxFuture<'static, Result<T, OAuth2Error>> {
+        let popup = callback::open_popup();
+        let window = popup.clone();
+        let (reply, rx) = oneshot::channel();
+        let msg = f(popup, Some(reply));
+        let tx = self.tx.clone();
+
+        async move {
+            if let Err(err) = tx.send(msg).await {
+                close_popup(window);
+                return Err(Error::from(err).into());
+            }
+            // the agent dropping the reply means it is gone
+            rx.await.map_err(|_| Error::NoAgent)?
+        }
+        .boxed_local()
     }
 }
 
@@ -657,32 +2516,279 @@ where
     C: Client,
 {
     fn configure(&self, config: AgentConfiguration<C>) -> Result<(), Error> {
-        self.tx
-            .try_send(Msg::Configure(config))
-            .map_err(|_| Error::NoAgent)
+        let response = InnerAgent::<C>::take_response(config.namespace.as_deref());
+        Ok(self
+            .tx
+            .try_send(Msg::Configure(Box::new(config), response, None))?)
     }
 
     fn start_login(&self) -> Result<(), Error> {
-        self.tx
-            .try_send(Msg::StartLogin(None))
-            .map_err(|_| Error::NoAgent)
+        Ok(self.tx.try_send(Msg::StartLogin(None, None))?)
     }
 
     fn start_login_opts(&self, options: LoginOptions) -> Result<(), Error> {
-        self.tx
-            .try_send(Msg::StartLogin(Some(options)))
-            .map_err(|_| Error::NoAgent)
+        Ok(self.tx.try_send(Msg::StartLogin(Some(options), None))?)
     }
 
     fn logout(&self) -> Result<(), Error> {
-        self.tx
-            .try_send(Msg::Logout(None))
-            .map_err(|_| Error::NoAgent)
+        Ok(self.tx.try_send(Msg::Logout(None, None))?)
     }
 
     fn logout_opts(&self, options: LogoutOptions) -> Result<(), Error> {
-        self.tx
-            .try_send(Msg::Logout(Some(options)))
-            .map_err(|_| Error::NoAgent)
+        Ok(self.tx.try_send(Msg::Logout(Some(options), None))?)
+    }
+}
+
+impl<C> OAuth2OperationsExt<C> for Agent<C>
+where
+    C: Client,
+{
+    fn start_login_popup(&self, options: LoginOptions) -> Result<(), Error> {
+        self.send_popup(|popup| Msg::StartLoginPopup(options, popup, None))
+    }
+
+    fn reset(&self) -> Result<(), Error> {
+        Ok(self.tx.try_send(Msg::Reset(None))?)
+    }
+
+    fn configure_async(
+        &self,
+        config: AgentConfiguration<C>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        let response = InnerAgent::<C>::take_response(config.namespace.as_deref());
+        self.request(|reply| Msg::Configure(Box::new(config), response, reply))
+    }
+
+    fn start_login_async(&self) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(|reply| Msg::StartLogin(None, reply))
+    }
+
+    fn start_login_opts_async(
+        &self,
+        options: LoginOptions,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(|reply| Msg::StartLogin(Some(options), reply))
+    }
+
+    fn start_login_popup_async(
+        &self,
+        options: LoginOptions,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request_popup(|popup, reply| Msg::StartLoginPopup(options, popup, reply))
+            .map(|result| result.map(|_| ()))
+            .boxed_local()
+    }
+
+    fn logout_async(&self) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(|reply| Msg::Logout(None, reply))
+    }
+
+    fn logout_opts_async(
+        &self,
+        options: LogoutOptions,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(|reply| Msg::Logout(Some(options), reply))
+    }
+
+    fn reset_async(&self) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(Msg::Reset)
+    }
+
+    fn refresh(&self) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>> {
+        self.request(|reply| Msg::Refresh(None, reply))
+    }
+
+    fn refresh_opts(
+        &self,
+        options: RefreshOptions,
+    ) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>> {
+        self.request(|reply| Msg::Refresh(Some(options), reply))
+    }
+
+    fn step_up(&self, scopes: Vec<String>) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(|reply| Msg::StepUp(scopes, vec![], reply))
+    }
+
+    fn step_up_popup(
+        &self,
+        scopes: Vec<String>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request_popup(|popup, reply| Msg::StepUpPopup(scopes, vec![], popup, reply))
+            .map(|result| result.map(|_| ()))
+            .boxed_local()
+    }
+
+    fn step_up_authorization_details(
+        &self,
+        authorization_details: Vec<AuthorizationDetail>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request(|reply| Msg::StepUp(vec![], authorization_details, reply))
+    }
+
+    fn step_up_authorization_details_popup(
+        &self,
+        authorization_details: Vec<AuthorizationDetail>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>> {
+        self.request_popup(|popup, reply| {
+            Msg::StepUpPopup(vec![], authorization_details, popup, reply)
+        })
+        .map(|result| result.map(|_| ()))
+        .boxed_local()
+    }
+
+    fn reauthenticate(
+        &self,
+        max_age: Duration,
+        acr_values: Vec<String>,
+    ) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>> {
+        self.request_popup(|popup, reply| Msg::Reauthenticate(max_age, acr_values, popup, reply))
+    }
+
+    fn extend_session(&self) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>> {
+        self.request(Msg::ExtendSession)
+    }
+
+    fn access_token(&self) -> LocalBoxFuture<'static, Result<String, OAuth2Error>> {
+        self.request(Msg::AccessToken)
+            .map(|result| result.map(|auth| auth.access_token))
+            .boxed_local()
+    }
+}
+
+/// Start a timer, firing after the duration.
+///
+/// Timers might be delayed or paused, so long durations are cut down to [`MAX_TIMER_CHUNK`],
+/// requiring the callback to re-check the time.
+fn chunked_timeout(duration: Duration, callback: impl FnOnce() + 'static) -> Timeout {
+    let millis = min(duration, MAX_TIMER_CHUNK)
+        .as_millis()
+        .to_u32()
+        .unwrap_or(u32::MAX);
+    log::debug!("Starting timeout for: {millis}ms");
+    Timeout::new(millis, callback)
+}
+
+/// The session to share with other tabs, or to persist for later page loads.
+///
+/// A session bound to a DPoP key is never shared, as the key can't leave the page. Other tabs or
+/// page loads would use the tokens with a different key, which the issuer rejects.
+fn shared_session<S: Clone>(
+    state: &OAuth2Context,
+    session_state: Option<&S>,
+) -> Option<(Authentication, S)> {
+    match (state, session_state) {
+        (OAuth2Context::Authenticated(auth), Some(session_state)) if auth.dpop.is_none() => {
+            Some((auth.clone(), session_state.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Check if the session of the state can be refreshed, or renewed.
+fn can_refresh(state: &OAuth2Context, config: Option<&InnerConfig>) -> bool {
+    match state {
+        OAuth2Context::Authenticated(auth) => {
+            auth.refresh_token.is_some()
+                || config.is_some_and(|config| config.silent_login.is_some())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn authenticated(dpop: Option<DpopKey>) -> OAuth2Context {
+        OAuth2Context::Authenticated(Authentication {
+            access_token: "token".into(),
+            refresh_token: Some("refresh".into()),
+            dpop,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn share_bearer_session() {
+        let state = authenticated(None);
+        let (auth, session_state) = shared_session(&state, Some(&"session")).unwrap();
+        assert_eq!(auth.access_token, "token");
+        assert_eq!(session_state, "session");
+    }
+
+    #[test]
+    fn keep_dpop_session() {
+        let state = authenticated(Some(DpopKey::test()));
+        assert!(shared_session(&state, Some(&"session")).is_none());
+    }
+
+    #[test]
+    fn deferred_before_expiry() {
+        assert_eq!(
+            Deferred::evaluate(100.0, 200.0, None),
+            Deferred::Pending {
+                token_expired: false,
+                next: Some(200.0)
+            }
+        );
+        assert_eq!(
+            Deferred::evaluate(100.0, 200.0, Some(300.0)),
+            Deferred::Pending {
+                token_expired: false,
+                next: Some(200.0)
+            }
+        );
+        assert_eq!(
+            Deferred::evaluate(100.0, 200.0, Some(150.0)),
+            Deferred::Pending {
+                token_expired: false,
+                next: Some(150.0)
+            }
+        );
+    }
+
+    #[test]
+    fn deferred_token_expired() {
+        assert_eq!(
+            Deferred::evaluate(200.0, 200.0, Some(300.0)),
+            Deferred::Pending {
+                token_expired: true,
+                next: Some(300.0)
+            }
+        );
+        // without a known expiration of the refresh token, there is nothing left to check
+        assert_eq!(
+            Deferred::evaluate(250.0, 200.0, None),
+            Deferred::Pending {
+                token_expired: true,
+                next: None
+            }
+        );
+    }
+
+    #[test]
+    fn deferred_refresh_expired() {
+        assert_eq!(
+            Deferred::evaluate(300.0, 200.0, Some(300.0)),
+            Deferred::RefreshExpired
+        );
+        assert_eq!(
+            Deferred::evaluate(150.0, 200.0, Some(100.0)),
+            Deferred::RefreshExpired
+        );
+    }
+
+    #[test]
+    fn share_no_session() {
+        assert!(shared_session(&authenticated(None), None::<&()>).is_none());
+        assert!(
+            shared_session(
+                &OAuth2Context::NotAuthenticated {
+                    reason: Reason::Logout
+                },
+                Some(&())
+            )
+            .is_none()
+        );
     }
 }
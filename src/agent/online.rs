@@ -0,0 +1,35 @@
+//! Tracking the network connectivity of the browser.
+
+use gloo_events::EventListener;
+use gloo_utils::window;
+
+/// Listens for the browser going offline, or coming back online.
+pub(crate) struct Connectivity {
+    _listeners: [EventListener; 2],
+}
+
+impl Connectivity {
+    /// Create a new listener, the callback receives `true` when the browser is online again.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(bool) + Clone + 'static,
+    {
+        let online = {
+            let callback = callback.clone();
+            EventListener::new(&window(), "online", move |_| callback(true))
+        };
+        let offline = EventListener::new(&window(), "offline", move |_| callback(false));
+
+        Self {
+            _listeners: [online, offline],
+        }
+    }
+}
+
+/// Check if the browser is online.
+///
+/// Browsers might report being online, even if there is no actual connectivity. However, if it
+/// reports being offline, there is no point in trying to contact the issuer.
+pub(crate) fn is_online() -> bool {
+    window().navigator().on_line()
+}
@@ -1,24 +1,54 @@
-use super::{AgentConfiguration, Client, LoginOptions, LogoutOptions};
-use std::fmt::{Display, Formatter};
+use super::{
+    AgentConfiguration, AuthorizationDetail, Client, LoginOptions, LogoutOptions, OAuth2Error,
+    RefreshOptions,
+};
+use crate::context::Authentication;
+use futures::future::LocalBoxFuture;
+use std::{
+    fmt::{Display, Formatter},
+    time::Duration,
+};
+use tokio::sync::mpsc::error::{SendError, TrySendError};
 
 /// Operation error
 #[derive(Clone, Debug)]
 pub enum Error {
     /// The agent cannot be reached.
     NoAgent,
+    /// The agent is busy and cannot accept more requests at the moment.
+    Full,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::NoAgent => write!(f, "no agent"),
+            Self::Full => write!(f, "agent is busy"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl<T> From<TrySendError<T>> for Error {
+    fn from(err: TrySendError<T>) -> Self {
+        match err {
+            TrySendError::Full(_) => Self::Full,
+            TrySendError::Closed(_) => Self::NoAgent,
+        }
+    }
+}
+
+impl<T> From<SendError<T>> for Error {
+    fn from(_: SendError<T>) -> Self {
+        Self::NoAgent
+    }
+}
+
 /// Operations for the OAuth2 agent
+///
+/// These operations only hand over the request to the agent, and return once the agent accepted
+/// it. For more operations, and awaiting the actual outcome, see [`OAuth2OperationsExt`].
 pub trait OAuth2Operations<C: Client> {
     /// Configure the agent with a configuration.
     ///
@@ -37,3 +67,153 @@ pub trait OAuth2Operations<C: Client> {
     /// Trigger the logout.
     fn logout_opts(&self, options: LogoutOptions) -> Result<(), Error>;
 }
+
+/// Additional operations for the OAuth2 agent
+///
+/// The `_async` variants of the [`OAuth2Operations`], and all other operations returning a
+/// future, wait for the agent to process the request, and report the actual outcome. The request
+/// is created when calling the function, the returned future only waits for the result. This
+/// allows operations using a popup window to open it while handling the user interaction.
+pub trait OAuth2OperationsExt<C: Client>: OAuth2Operations<C> {
+    /// Start a login flow in a popup window, without navigating away from the current page.
+    ///
+    /// The issuer redirects the popup to the `redirect_url` of the options, or the one of the
+    /// [`super::SilentLoginOptions`], which must point to a callback page reporting back to the
+    /// application. See [`super::SilentLoginOptions`] for an example of such a page.
+    ///
+    /// Browsers only allow opening a popup in response to a user interaction, so this must be
+    /// called directly by the handler of that interaction (e.g. a click callback).
+    fn start_login_popup(&self, options: LoginOptions) -> Result<(), Error>;
+
+    /// Reset the agent, e.g. to try again after a failure.
+    ///
+    /// This drops the current session, clears any pending login transaction, re-creates the
+    /// client, and moves to [`crate::context::OAuth2Context::NotAuthenticated`].
+    fn reset(&self) -> Result<(), Error>;
+
+    /// Configure the agent, and wait for the client to be created.
+    fn configure_async(
+        &self,
+        config: AgentConfiguration<C>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Start a login flow with default options, and wait for it to be started.
+    ///
+    /// As starting the login flow navigates away from the current page, this will most likely
+    /// only return in the case of an error.
+    fn start_login_async(&self) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Start a login flow, and wait for it to be started.
+    ///
+    /// See [`Self::start_login_async`].
+    fn start_login_opts_async(
+        &self,
+        options: LoginOptions,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Start a login flow in a popup window, and wait for the login to be completed.
+    ///
+    /// See [`Self::start_login_popup`]. The popup is opened when calling this function, not when
+    /// awaiting the returned future. So it must be called by the handler of the user interaction,
+    /// before spawning the future.
+    fn start_login_popup_async(
+        &self,
+        options: LoginOptions,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Trigger the logout with default options, and wait for it to be processed.
+    fn logout_async(&self) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Trigger the logout, and wait for it to be processed.
+    fn logout_opts_async(
+        &self,
+        options: LogoutOptions,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Reset the agent, and wait for the client to be re-created.
+    ///
+    /// See [`Self::reset`].
+    fn reset_async(&self) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Refresh the token now, with default options.
+    ///
+    /// This can be used when the token is known to be stale before it expires, e.g. after
+    /// receiving a `401` from an API. A refresh already in progress is joined, instead of
+    /// requesting another token.
+    fn refresh(&self) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>>;
+
+    /// Refresh the token now.
+    ///
+    /// See [`Self::refresh`]. Requests with different options are not joined, but performed one
+    /// after the other.
+    fn refresh_opts(
+        &self,
+        options: RefreshOptions,
+    ) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>>;
+
+    /// Request additional scopes for the current session, and wait for the login to be started.
+    ///
+    /// This starts a new login, requesting the provided scopes in addition to the configured
+    /// scopes and the scopes already granted. If all scopes are granted already, this does
+    /// nothing. Like [`Self::start_login_async`], this will most likely only return if there is
+    /// nothing to do, or in the case of an error.
+    fn step_up(&self, scopes: Vec<String>) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Request additional scopes for the current session, using a popup.
+    ///
+    /// See [`Self::step_up`] and [`Self::start_login_popup`]. Fails with
+    /// [`super::ErrorKind::AccessDenied`] if the issuer didn't grant the scopes when requesting
+    /// them the last time. Like [`Self::start_login_popup_async`], the popup is opened when
+    /// calling this function.
+    fn step_up_popup(
+        &self,
+        scopes: Vec<String>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Request additional authorization details for the current session.
+    ///
+    /// This starts a new login, requesting the provided details in addition to the details
+    /// already granted. If all details are granted already (compared by equality), this does
+    /// nothing. See [`Self::step_up`].
+    fn step_up_authorization_details(
+        &self,
+        authorization_details: Vec<AuthorizationDetail>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Request additional authorization details for the current session, using a popup.
+    ///
+    /// See [`Self::step_up_authorization_details`] and [`Self::start_login_popup_async`].
+    fn step_up_authorization_details_popup(
+        &self,
+        authorization_details: Vec<AuthorizationDetail>,
+    ) -> LocalBoxFuture<'static, Result<(), OAuth2Error>>;
+
+    /// Re-authenticate the user, e.g. before performing a sensitive action.
+    ///
+    /// This performs a popup login (see [`Self::start_login_popup`]), requiring the user to log
+    /// in again (`prompt=login`). The authentication must not be older than `max_age`, and match
+    /// one of the requested authentication context class references, if any. Both are verified
+    /// using the claims of the returned ID token, failing with
+    /// [`super::ErrorKind::InsufficientAuthentication`] otherwise.
+    ///
+    /// This requires OpenID Connect. On success, the new authentication is returned, replacing
+    /// the current one. On failure, the current session is kept. Like
+    /// [`Self::start_login_popup_async`], the popup is opened when calling this function.
+    fn reauthenticate(
+        &self,
+        max_age: Duration,
+        acr_values: Vec<String>,
+    ) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>>;
+
+    /// Extend the session, e.g. when the user confirms a warning about the session ending.
+    ///
+    /// This resets the idle timeout, and refreshes the token (or performs a silent login, if
+    /// there is no refresh token). The session limit can't be extended.
+    fn extend_session(&self) -> LocalBoxFuture<'static, Result<Authentication, OAuth2Error>>;
+
+    /// Get the access token of the current session.
+    ///
+    /// If the token is due for a refresh, according to the [`super::RefreshPolicy`], it will be
+    /// refreshed first. This is required when using the [`super::Lazy`] policy.
+    fn access_token(&self) -> LocalBoxFuture<'static, Result<String, OAuth2Error>>;
+}
@@ -0,0 +1,254 @@
+//! Pushed authorization requests (RFC 9126).
+
+use super::{ErrorKind, OAuth2Error};
+use oauth2::{AsyncHttpClient, http, url::form_urlencoded};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// The response of the pushed authorization request endpoint.
+#[derive(Debug, Deserialize)]
+struct PushedAuthorizationResponse {
+    request_uri: String,
+}
+
+/// An error response of the pushed authorization request endpoint.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+    error_uri: Option<String>,
+}
+
+/// Get the URL to navigate to for an authorization request.
+///
+/// If an endpoint for pushed authorization requests is provided, the parameters of the
+/// authorization request are sent to it, using the HTTP client of the token requests (which adds
+/// a DPoP proof, if enabled). The returned URL then only carries the `client_id` and the
+/// `request_uri`. Otherwise, the URL is returned as it is.
+pub(crate) async fn authorization_url<'c, H>(
+    endpoint: Option<&Url>,
+    http_client: &'c H,
+    url: Url,
+) -> Result<Url, OAuth2Error>
+where
+    H: AsyncHttpClient<'c>,
+{
+    match endpoint {
+        Some(endpoint) => push(endpoint, http_client, url).await,
+        None => Ok(url),
+    }
+}
+
+/// Push the parameters of the authorization request.
+async fn push<'c, H>(endpoint: &Url, http_client: &'c H, mut url: Url) -> Result<Url, OAuth2Error>
+where
+    H: AsyncHttpClient<'c>,
+{
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let client_id = params
+        .iter()
+        .find(|(key, _)| key == "client_id")
+        .map(|(_, value)| value.clone())
+        .ok_or_else(|| {
+            OAuth2Error::start_login("Authorization request is missing the client ID")
+        })?;
+
+    let body = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(&params)
+        .finish();
+    let request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(endpoint.as_str())
+        .header(
+            http::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        )
+        .header(http::header::ACCEPT, "application/json")
+        .body(body.into_bytes())
+        .map_err(|err| {
+            OAuth2Error::internal(format!(
+                "failed to create pushed authorization request: {err}"
+            ))
+        })?;
+
+    let response = http_client.call(request).await.map_err(|err| {
+        OAuth2Error::new(ErrorKind::Network)
+            .with_description(format!("failed to push authorization request: {err}"))
+    })?;
+
+    let status = response.status();
+    let body = response.body();
+
+    if !status.is_success() {
+        return Err(match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(error) => {
+                OAuth2Error::from_response(error.error, error.error_description, error.error_uri)
+            }
+            Err(_) => OAuth2Error::new(ErrorKind::Unavailable)
+                .with_description(format!("failed to push authorization request: {status}")),
+        });
+    }
+
+    let response: PushedAuthorizationResponse = serde_json::from_slice(body).map_err(|err| {
+        OAuth2Error::new(ErrorKind::Unavailable)
+            .with_description(format!("invalid pushed authorization response: {err}"))
+    })?;
+
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("client_id", &client_id)
+        .append_pair("request_uri", &response.request_uri);
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::agent::{DPOP_HEADER, DpopClient, DpopKey};
+    use futures::executor::block_on;
+    use oauth2::{HttpRequest, HttpResponse};
+    use std::{
+        cell::{Cell, RefCell},
+        convert::Infallible,
+    };
+
+    const AUTHORIZATION_URL: &str =
+        "https://issuer/auth?response_type=code&client_id=app&state=xyz&scope=openid+profile";
+
+    fn endpoint() -> Url {
+        Url::parse("https://issuer/par").unwrap()
+    }
+
+    fn response(status: u16, body: &str) -> HttpResponse {
+        http::Response::builder()
+            .status(status)
+            .body(body.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    fn push_with(status: u16, body: &'static str) -> (Result<Url, OAuth2Error>, Vec<HttpRequest>) {
+        let requests = RefCell::new(Vec::new());
+        // a stand-in endpoint for pushed authorization requests
+        let http_client = |request: HttpRequest| {
+            requests.borrow_mut().push(request);
+            async move { Ok::<_, Infallible>(response(status, body)) }
+        };
+
+        let result = block_on(authorization_url(
+            Some(&endpoint()),
+            &http_client,
+            Url::parse(AUTHORIZATION_URL).unwrap(),
+        ));
+        (result, requests.into_inner())
+    }
+
+    #[test]
+    fn without_endpoint() {
+        let calls = Cell::new(0);
+        let http_client = |_: HttpRequest| {
+            calls.set(calls.get() + 1);
+            async { Ok::<_, Infallible>(response(500, "")) }
+        };
+        let url = Url::parse(AUTHORIZATION_URL).unwrap();
+        assert_eq!(
+            block_on(authorization_url(None, &http_client, url.clone())).unwrap(),
+            url
+        );
+        assert_eq!(calls.get(), 0);
+    }
+
+    #[test]
+    fn push_request() {
+        let (result, requests) = push_with(
+            201,
+            r#"{"request_uri":"urn:ietf:params:oauth:request_uri:abc","expires_in":60}"#,
+        );
+
+        assert_eq!(
+            result.unwrap().as_str(),
+            "https://issuer/auth?client_id=app&request_uri=urn%3Aietf%3Aparams%3Aoauth%3Arequest_uri%3Aabc"
+        );
+
+        let [request] = requests.as_slice() else {
+            panic!("expected a single request: {requests:?}");
+        };
+        assert_eq!(request.method(), http::Method::POST);
+        assert_eq!(request.uri(), "https://issuer/par");
+        assert_eq!(
+            request.headers()[http::header::CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(
+            request.body().as_slice(),
+            b"response_type=code&client_id=app&state=xyz&scope=openid+profile"
+        );
+    }
+
+    #[test]
+    fn push_with_dpop() {
+        let key = DpopKey::test();
+        let proofs = RefCell::new(Vec::new());
+        let http_client = |request: HttpRequest| {
+            proofs
+                .borrow_mut()
+                .push(request.headers().get(DPOP_HEADER).cloned());
+            async { Ok::<_, Infallible>(response(201, r#"{"request_uri":"urn:abc"}"#)) }
+        };
+
+        let client = DpopClient::new(&http_client, Some(&key));
+        block_on(authorization_url(
+            Some(&endpoint()),
+            &client,
+            Url::parse(AUTHORIZATION_URL).unwrap(),
+        ))
+        .unwrap();
+
+        let proofs = proofs.into_inner();
+        assert_eq!(proofs.len(), 1);
+        assert!(proofs[0].is_some());
+    }
+
+    #[test]
+    fn error_response() {
+        let (result, _) = push_with(
+            400,
+            r#"{"error":"invalid_request","error_description":"unknown scope","error_uri":"https://issuer/errors"}"#,
+        );
+
+        let err = result.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Authorization);
+        assert_eq!(err.code.as_deref(), Some("invalid_request"));
+        assert_eq!(err.description.as_deref(), Some("unknown scope"));
+        assert_eq!(err.uri.as_deref(), Some("https://issuer/errors"));
+    }
+
+    #[test]
+    fn error_response_mapped_kind() {
+        let (result, _) = push_with(401, r#"{"error":"access_denied"}"#);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::AccessDenied);
+    }
+
+    #[test]
+    fn error_without_body() {
+        let (result, _) = push_with(503, "Service Unavailable");
+        assert_eq!(result.unwrap_err().kind, ErrorKind::Unavailable);
+    }
+
+    #[test]
+    fn invalid_response() {
+        let (result, _) = push_with(201, r#"{"expires_in":60}"#);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::Unavailable);
+    }
+
+    #[test]
+    fn missing_client_id() {
+        let http_client = |_: HttpRequest| async { Ok::<_, Infallible>(response(201, "{}")) };
+        let result = block_on(authorization_url(
+            Some(&endpoint()),
+            &http_client,
+            Url::parse("https://issuer/auth?response_type=code").unwrap(),
+        ));
+        assert_eq!(result.unwrap_err().kind, ErrorKind::StartLogin);
+    }
+}
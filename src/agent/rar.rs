@@ -0,0 +1,242 @@
+//! Rich authorization requests (RFC 9396).
+
+use super::OAuth2Error;
+use oauth2::ExtraTokenFields;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+/// The parameter carrying the authorization details, in requests and token responses.
+const AUTHORIZATION_DETAILS: &str = "authorization_details";
+
+/// An authorization detail, requesting or describing fine-grained access.
+///
+/// Next to the `type`, the common fields of RFC 9396 are available. Fields specific to the
+/// type are kept in `extra`. Alternatively, a detail can be converted from and to a typed
+/// struct:
+///
+/// ```rust
+/// # use serde::{Deserialize, Serialize};
+/// # use yew_oauth2::prelude::AuthorizationDetail;
+/// #[derive(Serialize, Deserialize)]
+/// struct PaymentInitiation {
+///     r#type: String,
+///     amount: String,
+///     currency: String,
+/// }
+///
+/// let detail = AuthorizationDetail::from_value(&PaymentInitiation {
+///     r#type: "payment_initiation".into(),
+///     amount: "123.50".into(),
+///     currency: "EUR".into(),
+/// })
+/// .unwrap();
+///
+/// assert_eq!(detail.r#type, "payment_initiation");
+/// let payment: PaymentInitiation = detail.to_typed().unwrap();
+/// assert_eq!(payment.currency, "EUR");
+/// ```
+///
+///**NOTE**: This is a non-exhaustive struct. See [`super::LoginOptions`] for an example on how to work with this.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizationDetail {
+    /// The type of the authorization detail, defining the other fields.
+    #[serde(rename = "type")]
+    pub r#type: String,
+
+    /// The locations of the resources (e.g. URLs of resource servers).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<String>,
+
+    /// The kinds of actions to be taken at the resource.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<String>,
+
+    /// The kinds of data being requested from the resource.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub datatypes: Vec<String>,
+
+    /// A specific resource available at the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+
+    /// The types or levels of privilege being requested at the resource.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub privileges: Vec<String>,
+
+    /// Additional fields, specific to the type.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl AuthorizationDetail {
+    pub fn new(r#type: impl Into<String>) -> Self {
+        Self {
+            r#type: r#type.into(),
+            locations: vec![],
+            actions: vec![],
+            datatypes: vec![],
+            identifier: None,
+            privileges: vec![],
+            extra: Map::new(),
+        }
+    }
+
+    /// Create the detail from a typed value, which must serialize to an object with a `type`.
+    pub fn from_value<T: Serialize>(value: &T) -> Result<Self, serde_json::Error> {
+        serde_json::to_value(value).and_then(serde_json::from_value)
+    }
+
+    /// Convert the detail into a typed value.
+    pub fn to_typed<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::to_value(self).and_then(serde_json::from_value)
+    }
+
+    /// Set the locations
+    pub fn with_locations(
+        mut self,
+        locations: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.locations = locations.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the actions
+    pub fn with_actions(mut self, actions: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.actions = actions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the data types
+    pub fn with_datatypes(
+        mut self,
+        datatypes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.datatypes = datatypes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the identifier
+    pub fn with_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.identifier = Some(identifier.into());
+        self
+    }
+
+    /// Set the privileges
+    pub fn with_privileges(
+        mut self,
+        privileges: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.privileges = privileges.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add a field, specific to the type
+    pub fn add_field(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Additional fields of the token response, carrying the granted authorization details.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizationDetailsFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
+}
+
+impl ExtraTokenFields for AuthorizationDetailsFields {}
+
+/// Encode the authorization details as request parameter, unless there are none.
+pub(crate) fn authorization_details_param(
+    details: &[AuthorizationDetail],
+) -> Result<Option<(&'static str, String)>, OAuth2Error> {
+    if details.is_empty() {
+        return Ok(None);
+    }
+
+    serde_json::to_string(details)
+        .map(|details| Some((AUTHORIZATION_DETAILS, details)))
+        .map_err(|err| {
+            OAuth2Error::internal(format!("failed to encode authorization details: {err}"))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn payment() -> AuthorizationDetail {
+        AuthorizationDetail::new("payment_initiation")
+            .with_locations(["https://bank.example/payments"])
+            .with_actions(["initiate", "status"])
+            .with_identifier("XYZ-123")
+            .add_field(
+                "instructedAmount",
+                json!({"currency": "EUR", "amount": "123.50"}),
+            )
+            .add_field("creditorName", "Merchant A")
+    }
+
+    #[test]
+    fn no_param_without_details() {
+        assert_eq!(authorization_details_param(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn param() {
+        let (name, value) = authorization_details_param(&[
+            payment(),
+            AuthorizationDetail::new("account_information").with_privileges(["read"]),
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(name, "authorization_details");
+        assert_eq!(
+            value,
+            concat!(
+                r#"[{"type":"payment_initiation","locations":["https://bank.example/payments"],"#,
+                r#""actions":["initiate","status"],"identifier":"XYZ-123","#,
+                r#""creditorName":"Merchant A","instructedAmount":{"amount":"123.50","currency":"EUR"}},"#,
+                r#"{"type":"account_information","privileges":["read"]}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let details = vec![payment(), AuthorizationDetail::new("openid_credential")];
+        let (_, value) = authorization_details_param(&details).unwrap().unwrap();
+
+        let parsed: Vec<AuthorizationDetail> = serde_json::from_str(&value).unwrap();
+        assert_eq!(parsed, details);
+    }
+
+    #[test]
+    fn extra_fields_from_response() {
+        let fields: AuthorizationDetailsFields = serde_json::from_value(json!({
+            "authorization_details": [{
+                "type": "payment_initiation",
+                "actions": ["initiate"],
+                "creditorName": "Merchant A",
+            }],
+        }))
+        .unwrap();
+
+        let details = fields.authorization_details.unwrap();
+        assert_eq!(details.len(), 1);
+        assert_eq!(details[0].r#type, "payment_initiation");
+        assert_eq!(details[0].actions, vec!["initiate"]);
+        assert!(details[0].locations.is_empty());
+        assert_eq!(details[0].extra["creditorName"], "Merchant A");
+    }
+
+    #[test]
+    fn missing_details_in_response() {
+        let fields: AuthorizationDetailsFields = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(fields.authorization_details, None);
+    }
+}
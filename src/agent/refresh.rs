@@ -0,0 +1,178 @@
+//! Policies for deciding when to refresh a token.
+
+use js_sys::Math;
+use std::{fmt::Debug, rc::Rc, time::Duration};
+
+/// The lifetime of a token, in seconds since the epoch.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenLifetime {
+    /// The time the token was issued.
+    pub issued: f64,
+    /// The time the token expires.
+    pub expires: f64,
+}
+
+impl TokenLifetime {
+    pub fn new(issued: f64, expires: f64) -> Self {
+        Self { issued, expires }
+    }
+
+    /// The duration of the lifetime, in seconds.
+    pub fn duration(&self) -> f64 {
+        (self.expires - self.issued).max(0.0)
+    }
+}
+
+/// A policy, deciding when to refresh a token.
+///
+/// The agent evaluates the policy whenever it receives a new token. See [`GracePeriod`],
+/// [`Percentage`], [`Jitter`], and [`Lazy`] for the built-in policies.
+pub trait RefreshPolicy: Debug {
+    /// The time (in seconds since the epoch) at which the token should be refreshed.
+    fn refresh_at(&self, token: &TokenLifetime) -> f64;
+
+    /// If the token should only be refreshed when it is being requested.
+    ///
+    /// In this case, the agent doesn't refresh the token on its own, but only when calling
+    /// [`super::OAuth2OperationsExt::access_token`] after the time of the refresh.
+    fn lazy(&self) -> bool {
+        false
+    }
+}
+
+/// Refresh the token a fixed amount of time before it expires.
+///
+/// This is the default, using the `grace_period` of the
+/// [`crate::components::context::OAuth2`] component.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GracePeriod(pub Duration);
+
+impl RefreshPolicy for GracePeriod {
+    fn refresh_at(&self, token: &TokenLifetime) -> f64 {
+        token.expires - self.0.as_secs_f64()
+    }
+}
+
+/// Refresh the token once a fraction (`0.0` to `1.0`) of its lifetime has passed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Percentage(pub f64);
+
+impl RefreshPolicy for Percentage {
+    fn refresh_at(&self, token: &TokenLifetime) -> f64 {
+        token.issued + token.duration() * self.0.clamp(0.0, 1.0)
+    }
+}
+
+/// Refresh the token up to a random amount of time earlier than the wrapped policy.
+///
+/// This avoids many clients, which received their tokens at the same time, refreshing them at
+/// the same time too. The time of the refresh will not be before the token was issued.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Jitter<P: RefreshPolicy> {
+    /// The wrapped policy.
+    pub policy: P,
+    /// The maximum amount of time to refresh earlier.
+    pub max: Duration,
+}
+
+impl<P: RefreshPolicy> Jitter<P> {
+    pub fn new(policy: P, max: Duration) -> Self {
+        Self { policy, max }
+    }
+}
+
+impl<P: RefreshPolicy> RefreshPolicy for Jitter<P> {
+    fn refresh_at(&self, token: &TokenLifetime) -> f64 {
+        let refresh_at = self.policy.refresh_at(token) - self.max.as_secs_f64() * Math::random();
+        refresh_at.max(token.issued)
+    }
+
+    fn lazy(&self) -> bool {
+        self.policy.lazy()
+    }
+}
+
+/// Only refresh the token when it is being requested, and the wrapped policy considers it due.
+///
+/// See [`super::OAuth2OperationsExt::access_token`]. An expired token which can be refreshed
+/// doesn't end the session, as it will be refreshed with the next request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lazy<P: RefreshPolicy>(pub P);
+
+impl<P: RefreshPolicy> RefreshPolicy for Lazy<P> {
+    fn refresh_at(&self, token: &TokenLifetime) -> f64 {
+        self.0.refresh_at(token)
+    }
+
+    fn lazy(&self) -> bool {
+        true
+    }
+}
+
+/// Compare two optional policies, by their instance.
+pub(crate) fn same_policy(
+    a: &Option<Rc<dyn RefreshPolicy>>,
+    b: &Option<Rc<dyn RefreshPolicy>>,
+) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TOKEN: TokenLifetime = TokenLifetime {
+        issued: 1000.0,
+        expires: 1600.0,
+    };
+
+    #[test]
+    fn lifetime_duration() {
+        assert_eq!(TOKEN.duration(), 600.0);
+        // an expiration before the issue time doesn't result in a negative duration
+        assert_eq!(TokenLifetime::new(1000.0, 900.0).duration(), 0.0);
+    }
+
+    #[test]
+    fn grace_period() {
+        let policy = GracePeriod(Duration::from_secs(30));
+        assert_eq!(policy.refresh_at(&TOKEN), 1570.0);
+        assert!(!policy.lazy());
+    }
+
+    #[test]
+    fn percentage() {
+        assert_eq!(Percentage(0.0).refresh_at(&TOKEN), 1000.0);
+        assert_eq!(Percentage(0.75).refresh_at(&TOKEN), 1450.0);
+        assert_eq!(Percentage(1.0).refresh_at(&TOKEN), 1600.0);
+    }
+
+    #[test]
+    fn percentage_clamped() {
+        assert_eq!(Percentage(-1.0).refresh_at(&TOKEN), 1000.0);
+        assert_eq!(Percentage(2.0).refresh_at(&TOKEN), 1600.0);
+    }
+
+    #[test]
+    fn lazy() {
+        let policy = Lazy(Percentage(0.5));
+        assert_eq!(policy.refresh_at(&TOKEN), 1300.0);
+        assert!(policy.lazy());
+    }
+
+    #[test]
+    fn compare_policies() {
+        let a: Rc<dyn RefreshPolicy> = Rc::new(Percentage(0.5));
+        let b: Rc<dyn RefreshPolicy> = Rc::new(Percentage(0.5));
+
+        assert!(same_policy(&None, &None));
+        assert!(same_policy(&Some(a.clone()), &Some(a.clone())));
+        assert!(!same_policy(&Some(a.clone()), &Some(b)));
+        assert!(!same_policy(&Some(a), &None));
+    }
+}
@@ -0,0 +1,168 @@
+//! Retrying a failed token refresh, with an exponential backoff.
+
+use js_sys::Math;
+use std::time::Duration;
+
+/// Options for retrying a failed token refresh.
+///
+/// A refresh which failed with a temporary error (like a network issue, or the issuer being
+/// temporarily unavailable) will be retried using an exponential backoff, until it succeeds, the
+/// maximum number of retries is reached, or the token expires.
+///
+///**NOTE**: This is a non-exhaustive struct. See [`super::LoginOptions`] for an example on how to work with this.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryOptions {
+    /// The delay before the first retry.
+    pub initial_delay: Duration,
+    /// The maximum delay between two retries.
+    pub max_delay: Duration,
+    /// The factor the delay grows by with each retry.
+    pub multiplier: f64,
+    /// The amount of random jitter, as a fraction of the delay (`0.0` to `1.0`).
+    pub jitter: f64,
+    /// The maximum number of retries, or [`None`] to retry until the token expires.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_retries: None,
+        }
+    }
+}
+
+impl RetryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disable retrying
+    pub fn disabled() -> Self {
+        Self::default().with_max_retries(0)
+    }
+
+    /// Set the delay before the first retry
+    pub fn with_initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// Set the maximum delay between two retries
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Set the factor the delay grows by with each retry
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set the amount of random jitter
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the maximum number of retries
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Get the delay before the next retry, or [`None`] if no more retries should be performed.
+    ///
+    /// The attempt starts with zero for the first retry.
+    pub(crate) fn delay(&self, attempt: u32) -> Option<Duration> {
+        self.delay_with(attempt, Math::random())
+    }
+
+    /// Get the delay, using the provided random value (`0.0` to `1.0`) for the jitter.
+    fn delay_with(&self, attempt: u32, random: f64) -> Option<Duration> {
+        if self.max_retries.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powf(attempt as f64);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let delay = delay * (1.0 + jitter * (2.0 * random - 1.0));
+
+        // the jitter might push a large maximum delay beyond the range of a duration
+        Some(Duration::try_from_secs_f64(delay.max(0.0)).unwrap_or(self.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options() -> RetryOptions {
+        RetryOptions::new()
+            .with_initial_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(10))
+            .with_multiplier(2.0)
+            .with_jitter(0.0)
+    }
+
+    #[test]
+    fn backoff_grows() {
+        let options = options();
+        let delays: Vec<_> = (0..6)
+            .map(|attempt| options.delay_with(attempt, 0.5).unwrap().as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let options = options().with_jitter(0.2);
+
+        // the delay of the attempt is 4 seconds, varying by 20%
+        assert_eq!(
+            options.delay_with(2, 0.0),
+            Some(Duration::from_secs_f64(3.2))
+        );
+        assert_eq!(options.delay_with(2, 0.5), Some(Duration::from_secs(4)));
+        assert_eq!(
+            options.delay_with(2, 1.0),
+            Some(Duration::from_secs_f64(4.8))
+        );
+
+        // the jitter is applied after capping with the maximum delay
+        assert_eq!(options.delay_with(10, 1.0), Some(Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn jitter_clamped() {
+        let options = options().with_jitter(5.0);
+        assert_eq!(options.delay_with(0, 0.0), Some(Duration::ZERO));
+        assert_eq!(options.delay_with(0, 1.0), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn max_retries() {
+        let limited = options().with_max_retries(3);
+        assert!(limited.delay_with(0, 0.5).is_some());
+        assert!(limited.delay_with(2, 0.5).is_some());
+        assert_eq!(limited.delay_with(3, 0.5), None);
+        assert_eq!(limited.delay_with(4, 0.5), None);
+
+        assert_eq!(RetryOptions::disabled().delay_with(0, 0.5), None);
+        assert!(options().delay_with(1_000, 0.5).is_some());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let options = options().with_max_delay(Duration::MAX).with_jitter(1.0);
+        assert_eq!(options.delay_with(1_000, 1.0), Some(Duration::MAX));
+    }
+}
@@ -0,0 +1,165 @@
+//! Silent login, running the authorization request in a hidden iframe.
+
+use super::{OAuth2Error, callback};
+use gloo_utils::{body, document};
+use reqwest::Url;
+use std::time::Duration;
+use wasm_bindgen::JsCast;
+use web_sys::HtmlIFrameElement;
+
+/// Options for the silent login process
+///
+/// When enabled, the agent will try to restore a session by running the authorization request
+/// with `prompt=none` in a hidden iframe, instead of requiring the user to navigate to the
+/// issuer. The same mechanism is used to renew a session when the issuer didn't provide a
+/// refresh token.
+///
+/// ## Callback page
+///
+/// The issuer redirects the iframe to the `redirect_url`, which must point to a small page
+/// handing the response back to the application. It must not load the application itself,
+/// as that would process the response a second time. The following page is sufficient, and can
+/// also be used for popup based logins:
+///
+/// ```html
+/// <!DOCTYPE html>
+/// <html>
+///   <body>
+///     <script>(window.opener || window.parent).postMessage(location.href, location.origin);</script>
+///   </body>
+/// </html>
+/// ```
+///
+/// The page must be served from the same origin as the application, and registered as a valid
+/// redirect URL with the issuer.
+///
+///**NOTE**: This is a non-exhaustive struct. See [`super::LoginOptions`] for an example on how to work with this.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SilentLoginOptions {
+    /// The URL of the callback page, loaded by the hidden iframe.
+    pub redirect_url: Url,
+
+    /// The time to wait for a response from the issuer.
+    pub timeout: Duration,
+
+    /// Send the current ID token as `id_token_hint`, if one is available.
+    pub id_token_hint: bool,
+}
+
+impl SilentLoginOptions {
+    pub fn new(redirect_url: impl Into<Url>) -> Self {
+        Self {
+            redirect_url: redirect_url.into(),
+            timeout: Duration::from_secs(10),
+            id_token_hint: true,
+        }
+    }
+
+    /// Set the time to wait for a response from the issuer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set whether to send an `id_token_hint`
+    pub fn with_id_token_hint(mut self, id_token_hint: bool) -> Self {
+        self.id_token_hint = id_token_hint;
+        self
+    }
+}
+
+/// Turn an authorization URL into a silent one, adding the `query` and the `id_token_hint`.
+///
+/// Any `prompt` already present, in the URL or the query, is replaced with `prompt=none`, as
+/// the issuer must reject a `none` combined with other values.
+pub(crate) fn silent_url<'a>(
+    mut url: Url,
+    query: impl IntoIterator<Item = (&'a String, &'a String)>,
+    id_token_hint: Option<&str>,
+) -> Url {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .into_owned()
+        .chain(query.into_iter().map(|(k, v)| (k.clone(), v.clone())))
+        .filter(|(key, _)| key != "prompt")
+        .collect();
+    pairs.push(("prompt".to_string(), "none".to_string()));
+    if let Some(hint) = id_token_hint {
+        pairs.push(("id_token_hint".to_string(), hint.to_string()));
+    }
+
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url
+}
+
+/// Load the authorization URL in a hidden iframe, and wait for the callback page to report back.
+pub(crate) async fn authorize(url: &Url, timeout: Duration) -> Result<Url, OAuth2Error> {
+    let iframe = document()
+        .create_element("iframe")
+        .ok()
+        .and_then(|element| element.dyn_into::<HtmlIFrameElement>().ok())
+        .ok_or_else(|| OAuth2Error::internal("unable to create iframe".to_string()))?;
+
+    iframe.set_hidden(true);
+    iframe.set_src(url.as_str());
+
+    body()
+        .append_child(&iframe)
+        .map_err(|_| OAuth2Error::internal("unable to attach iframe".to_string()))?;
+
+    let result = match iframe.content_window() {
+        Some(source) => callback::receive(source, Some(timeout)).await,
+        None => Err(OAuth2Error::internal(
+            "iframe has no content window".to_string(),
+        )),
+    };
+
+    iframe.remove();
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn prompts(url: &Url) -> Vec<String> {
+        url.query_pairs()
+            .filter(|(key, _)| key == "prompt")
+            .map(|(_, value)| value.into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn adds_prompt_none() {
+        let url = silent_url(
+            Url::parse("https://issuer/auth?client_id=app&state=xyz").unwrap(),
+            &HashMap::new(),
+            None,
+        );
+        assert_eq!(
+            url.as_str(),
+            "https://issuer/auth?client_id=app&state=xyz&prompt=none"
+        );
+    }
+
+    #[test]
+    fn replaces_existing_prompt() {
+        let query = HashMap::from([
+            ("prompt".to_string(), "consent".to_string()),
+            ("ui_locales".to_string(), "de".to_string()),
+        ]);
+        let url = silent_url(
+            Url::parse("https://issuer/auth?client_id=app&prompt=login&state=xyz").unwrap(),
+            &query,
+            Some("token"),
+        );
+        assert_eq!(prompts(&url), vec!["none"]);
+        assert_eq!(
+            url.as_str(),
+            "https://issuer/auth?client_id=app&state=xyz&ui_locales=de&prompt=none&id_token_hint=token"
+        );
+    }
+}
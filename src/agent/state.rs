@@ -1,32 +1,242 @@
-use super::OAuth2Error;
-use gloo_storage::errors::StorageError;
-use gloo_storage::{SessionStorage, Storage};
-use std::fmt::Display;
+use super::{OAuth2Error, StateStore, WebStorageStore};
+use oauth2::url::form_urlencoded;
+use reqwest::Url;
+use serde::{Serialize, de::DeserializeOwned};
+use std::{collections::HashMap, fmt::Display};
 
 pub(crate) const STORAGE_KEY_CSRF_TOKEN: &str = "ctron/oauth2/csrfToken";
 pub(crate) const STORAGE_KEY_LOGIN_STATE: &str = "ctron/oauth2/loginState";
 pub(crate) const STORAGE_KEY_REDIRECT_URL: &str = "ctron/oauth2/redirectUrl";
 pub(crate) const STORAGE_KEY_POST_LOGIN_URL: &str = "ctron/oauth2/postLoginUrl";
+pub(crate) const STORAGE_KEY_SCOPES: &str = "ctron/oauth2/scopes";
+pub(crate) const STORAGE_KEY_SESSION: &str = "ctron/oauth2/session";
 
+/// Keys holding the transient state of a login flow.
+pub(crate) const STORAGE_KEYS_TRANSACTION: &[&str] = &[
+    STORAGE_KEY_CSRF_TOKEN,
+    STORAGE_KEY_LOGIN_STATE,
+    STORAGE_KEY_REDIRECT_URL,
+    STORAGE_KEY_POST_LOGIN_URL,
+    STORAGE_KEY_SCOPES,
+];
+
+/// Separates the namespace from the random part of the `state` parameter.
+const STATE_TAG_SEPARATOR: char = '.';
+
+/// Tag the `state` parameter of a login with the namespace of the agent, if any.
+pub(crate) fn tag_state(state: String, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) => format!("{namespace}{STATE_TAG_SEPARATOR}{state}"),
+        None => state,
+    }
+}
+
+/// Parameters of an authorization response, removed from the URL after processing it.
+const RESPONSE_PARAMS: &[&str] = &[
+    "code",
+    "state",
+    "error",
+    "error_description",
+    "error_uri",
+    "iss",
+    "session_state",
+];
+
+/// Remove the parameters of the authorization response, keeping all others as they are.
+fn strip_response_params(params: &str) -> String {
+    params
+        .split('&')
+        .filter(|pair| {
+            form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .is_some_and(|(key, _)| !RESPONSE_PARAMS.contains(&key.as_ref()))
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The part of a URL, carrying the authorization response.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseLocation {
+    /// The query (`?code=...`).
+    Query,
+    /// The fragment (`#code=...`).
+    Fragment,
+    /// The query of a hash-based route (`#/route?code=...`).
+    HashRoute,
+}
+
+impl ResponseLocation {
+    /// The parameters of this part of the URL, if present.
+    fn params(self, url: &Url) -> Option<&str> {
+        match self {
+            Self::Query => url.query(),
+            Self::Fragment => url.fragment().filter(|fragment| !fragment.contains('?')),
+            Self::HashRoute => url
+                .fragment()
+                .and_then(|fragment| fragment.split_once('?'))
+                .map(|(_, query)| query),
+        }
+    }
+
+    /// Remove the response parameters from this part of the URL.
+    ///
+    /// Other parameters, and the route of a hash-based route, are kept. If no parameters remain,
+    /// the part is removed.
+    pub fn remove(self, url: &mut Url) {
+        let Some(params) = self.params(url) else {
+            return;
+        };
+        let params = strip_response_params(params);
+        let params = (!params.is_empty()).then_some(params);
+
+        match self {
+            Self::Query => url.set_query(params.as_deref()),
+            Self::Fragment => url.set_fragment(params.as_deref()),
+            Self::HashRoute => {
+                let route = url
+                    .fragment()
+                    .and_then(|fragment| fragment.split_once('?'))
+                    .map(|(route, _)| route.to_string())
+                    .unwrap_or_default();
+                let fragment = match params {
+                    Some(params) => format!("{route}?{params}"),
+                    None => route,
+                };
+                url.set_fragment(Some(&fragment));
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
 #[derive(Debug)]
-pub(crate) struct State {
+pub struct State {
     pub code: Option<String>,
     pub state: Option<String>,
     pub error: Option<String>,
+    pub error_description: Option<String>,
+    pub error_uri: Option<String>,
+    /// The part of the URL, the state was found in.
+    pub location: ResponseLocation,
+}
+
+impl State {
+    /// Extract the state from a URL.
+    ///
+    /// The response is looked up in the query, the fragment, and the query of a hash-based
+    /// route, using the first one containing a code or an error.
+    pub fn from_url(url: &Url) -> Self {
+        [
+            ResponseLocation::Query,
+            ResponseLocation::Fragment,
+            ResponseLocation::HashRoute,
+        ]
+        .into_iter()
+        .filter_map(|location| {
+            location
+                .params(url)
+                .map(|params| Self::from_params(params, location))
+        })
+        .find(|state| state.code.is_some() || state.error.is_some())
+        .unwrap_or_else(|| {
+            Self::from_params(url.query().unwrap_or_default(), ResponseLocation::Query)
+        })
+    }
+
+    fn from_params(params: &str, location: ResponseLocation) -> Self {
+        let params: HashMap<_, _> = form_urlencoded::parse(params.as_bytes()).collect();
+
+        Self {
+            code: params.get("code").map(ToString::to_string),
+            state: params.get("state").map(ToString::to_string),
+            error: params.get("error").map(ToString::to_string),
+            error_description: params.get("error_description").map(ToString::to_string),
+            error_uri: params.get("error_uri").map(ToString::to_string),
+            location,
+        }
+    }
+
+    /// Check if the response belongs to an agent of the provided namespace.
+    ///
+    /// The `state` of a namespaced agent is tagged with its namespace (see [`tag_state`]). A
+    /// response without a tag belongs to an agent without a namespace. This includes responses
+    /// without a `state`, like some error responses. A `state` containing the separator is
+    /// considered tagged, even if it wasn't created by a namespaced agent.
+    pub fn is_for(&self, namespace: Option<&str>) -> bool {
+        let tag = self
+            .state
+            .as_deref()
+            .and_then(|state| state.rsplit_once(STATE_TAG_SEPARATOR))
+            .map(|(tag, _)| tag);
+        tag == namespace
+    }
+
+    /// Get the error response of the issuer, if any.
+    pub fn error(&self) -> Option<OAuth2Error> {
+        self.error.as_ref().map(|error| {
+            OAuth2Error::from_response(
+                error,
+                self.error_description.clone(),
+                self.error_uri.clone(),
+            )
+        })
+    }
+}
+
+/// Load and decode a value from the store.
+pub(crate) async fn load<T: DeserializeOwned>(
+    store: &dyn StateStore,
+    key: &str,
+) -> Result<Option<T>, OAuth2Error> {
+    decode(key, store.get(key).await?)
+}
+
+/// Decode a value, read from a store.
+fn decode<T: DeserializeOwned>(key: &str, value: Option<String>) -> Result<Option<T>, OAuth2Error> {
+    value
+        .map(|value| {
+            serde_json::from_str(&value).map_err(|err| {
+                OAuth2Error::storage(format!("Failed to decode value for key {key}: {err}"))
+            })
+        })
+        .transpose()
+}
+
+/// Encode and store a value.
+pub(crate) async fn save<T: Serialize + ?Sized>(
+    store: &dyn StateStore,
+    key: &str,
+    value: &T,
+) -> Result<(), OAuth2Error> {
+    let value = serde_json::to_string(value).map_err(|err| {
+        OAuth2Error::storage(format!("Failed to encode value for key {key}: {err}"))
+    })?;
+    store.set(key, &value).await
 }
 
-pub(crate) fn get_from_store<K: AsRef<str> + Display>(key: K) -> Result<String, OAuth2Error> {
-    get_from_store_optional(&key)?.ok_or_else(|| OAuth2Error::storage_key_empty(key))
+pub(crate) async fn get_from_store<K: AsRef<str> + Display>(
+    store: &dyn StateStore,
+    key: K,
+) -> Result<String, OAuth2Error> {
+    get_from_store_optional(store, &key)
+        .await?
+        .ok_or_else(|| OAuth2Error::storage_key_empty(key))
 }
 
-pub(crate) fn get_from_store_optional<K: AsRef<str> + Display>(
+pub(crate) async fn get_from_store_optional<K: AsRef<str> + Display>(
+    store: &dyn StateStore,
     key: K,
 ) -> Result<Option<String>, OAuth2Error> {
-    match SessionStorage::get::<String>(key.as_ref()) {
-        Err(StorageError::KeyNotFound(_)) => Ok(None),
-        Err(err) => Err(OAuth2Error::Storage(err.to_string())),
-        Ok(value) if value.is_empty() => Err(OAuth2Error::storage_key_empty(key)),
-        Ok(value) => Ok(Some(value)),
+    non_empty(key.as_ref(), load::<String>(store, key.as_ref()).await?)
+}
+
+fn non_empty<K: Display>(key: K, value: Option<String>) -> Result<Option<String>, OAuth2Error> {
+    match value {
+        None => Ok(None),
+        Some(value) if value.is_empty() => Err(OAuth2Error::storage_key_empty(key)),
+        Some(value) => Ok(Some(value)),
     }
 }
 
@@ -38,11 +248,193 @@ pub struct LoginState {
 }
 
 impl LoginState {
-    /// Read the state from the session
+    /// Read the state from the browser's session storage
+    ///
+    /// This only finds the state of an agent using the default storage backend and no namespace.
+    /// Otherwise, use [`Self::from_store`].
     pub fn from_storage() -> Result<Self, OAuth2Error> {
+        let store = WebStorageStore::session()?;
+        let get = |key| non_empty(key, decode(key, store.get_now(key)?)?);
+        Ok(Self {
+            redirect_url: get(STORAGE_KEY_REDIRECT_URL)?,
+            post_login_url: get(STORAGE_KEY_POST_LOGIN_URL)?,
+        })
+    }
+
+    /// Read the state from the store
+    pub async fn from_store(store: &dyn StateStore) -> Result<Self, OAuth2Error> {
         Ok(Self {
-            redirect_url: get_from_store_optional(STORAGE_KEY_REDIRECT_URL)?,
-            post_login_url: get_from_store_optional(STORAGE_KEY_POST_LOGIN_URL)?,
+            redirect_url: get_from_store_optional(store, STORAGE_KEY_REDIRECT_URL).await?,
+            post_login_url: get_from_store_optional(store, STORAGE_KEY_POST_LOGIN_URL).await?,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn state(state: Option<&str>) -> State {
+        State {
+            state: state.map(ToString::to_string),
+            ..State::from_url(&url("https://app/"))
+        }
+    }
+
+    #[test]
+    fn tag_with_namespace() {
+        assert_eq!(tag_state("xyz".to_string(), None), "xyz");
+        assert_eq!(tag_state("xyz".to_string(), Some("app")), "app.xyz");
+    }
+
+    #[test]
+    fn match_namespace() {
+        let tagged = state(Some(&tag_state("xyz".to_string(), Some("app"))));
+        assert!(tagged.is_for(Some("app")));
+        assert!(!tagged.is_for(Some("other")));
+        assert!(!tagged.is_for(None));
+
+        let untagged = state(Some(&tag_state("xyz".to_string(), None)));
+        assert!(untagged.is_for(None));
+        assert!(!untagged.is_for(Some("app")));
+    }
+
+    #[test]
+    fn match_namespace_with_separator() {
+        let tagged = state(Some(&tag_state("xyz".to_string(), Some("my.app"))));
+        assert!(tagged.is_for(Some("my.app")));
+        assert!(!tagged.is_for(Some("my")));
+        assert!(!tagged.is_for(None));
+    }
+
+    #[test]
+    fn untagged_state_with_separator() {
+        // a state with the separator, not created by the agent, is not claimed without namespace
+        let foreign = state(Some("some.state"));
+        assert!(!foreign.is_for(None));
+        assert!(foreign.is_for(Some("some")));
+    }
+
+    #[test]
+    fn missing_state() {
+        // e.g. an error response, only claimed by the agent without namespace
+        let state = State::from_url(&url("https://app/callback?error=access_denied"));
+        assert!(state.is_for(None));
+        assert!(!state.is_for(Some("app")));
+    }
+
+    #[test]
+    fn response_in_query() {
+        let state = State::from_url(&url("https://app/callback?code=abc&state=xyz"));
+        assert_eq!(state.location, ResponseLocation::Query);
+        assert_eq!(state.code.as_deref(), Some("abc"));
+        assert_eq!(state.state.as_deref(), Some("xyz"));
+    }
+
+    #[test]
+    fn response_in_fragment() {
+        let state = State::from_url(&url("https://app/callback#code=abc&state=xyz"));
+        assert_eq!(state.location, ResponseLocation::Fragment);
+        assert_eq!(state.code.as_deref(), Some("abc"));
+        assert_eq!(state.state.as_deref(), Some("xyz"));
+    }
+
+    #[test]
+    fn error_in_fragment() {
+        let state = State::from_url(&url(
+            "https://app/callback#error=access_denied&error_description=Denied%20by%20user",
+        ));
+        assert_eq!(state.location, ResponseLocation::Fragment);
+        assert_eq!(state.code, None);
+        assert_eq!(state.error.as_deref(), Some("access_denied"));
+        assert_eq!(state.error_description.as_deref(), Some("Denied by user"));
+    }
+
+    #[test]
+    fn response_in_hash_route() {
+        let state = State::from_url(&url("https://app/#/callback?code=abc&state=xyz"));
+        assert_eq!(state.location, ResponseLocation::HashRoute);
+        assert_eq!(state.code.as_deref(), Some("abc"));
+        assert_eq!(state.state.as_deref(), Some("xyz"));
+    }
+
+    #[test]
+    fn hash_route_without_query() {
+        let mut url = url("https://app/#/dashboard");
+        let state = State::from_url(&url);
+        assert_eq!(state.code, None);
+        assert_eq!(state.error, None);
+
+        state.location.remove(&mut url);
+        assert_eq!(url.as_str(), "https://app/#/dashboard");
+    }
+
+    #[test]
+    fn query_takes_precedence() {
+        let state = State::from_url(&url("https://app/?code=query#code=fragment"));
+        assert_eq!(state.location, ResponseLocation::Query);
+        assert_eq!(state.code.as_deref(), Some("query"));
+    }
+
+    #[test]
+    fn no_response() {
+        let state = State::from_url(&url("https://app/page?foo=bar#section"));
+        assert_eq!(state.location, ResponseLocation::Query);
+        assert_eq!(state.code, None);
+        assert_eq!(state.error, None);
+    }
+
+    #[test]
+    fn strip_keeps_other_params() {
+        assert_eq!(
+            strip_response_params(
+                "foo=1&code=abc&bar=a%20b&state=xyz&iss=https%3A%2F%2Fissuer&baz"
+            ),
+            "foo=1&bar=a%20b&baz"
+        );
+        assert_eq!(
+            strip_response_params("error=access_denied&error_description=Denied&error_uri=x"),
+            ""
+        );
+    }
+
+    #[test]
+    fn remove_keeps_other_params_and_fragment() {
+        let mut url =
+            url("https://app/page?foo=1&code=abc&bar=2&session_state=s&state=xyz#section");
+        ResponseLocation::Query.remove(&mut url);
+        assert_eq!(url.as_str(), "https://app/page?foo=1&bar=2#section");
+    }
+
+    #[test]
+    fn remove_keeps_other_params_of_hash_route() {
+        let mut url = url("https://app/?foo=1#/callback?tab=2&code=abc&state=xyz&view=list");
+        ResponseLocation::HashRoute.remove(&mut url);
+        assert_eq!(url.as_str(), "https://app/?foo=1#/callback?tab=2&view=list");
+    }
+
+    #[test]
+    fn remove_from_query() {
+        let mut url = url("https://app/callback?code=abc&state=xyz");
+        ResponseLocation::Query.remove(&mut url);
+        assert_eq!(url.as_str(), "https://app/callback");
+    }
+
+    #[test]
+    fn remove_from_fragment() {
+        let mut url = url("https://app/callback#code=abc&state=xyz");
+        ResponseLocation::Fragment.remove(&mut url);
+        assert_eq!(url.as_str(), "https://app/callback");
+    }
+
+    #[test]
+    fn remove_from_hash_route() {
+        let mut url = url("https://app/#/callback?code=abc&state=xyz");
+        ResponseLocation::HashRoute.remove(&mut url);
+        assert_eq!(url.as_str(), "https://app/#/callback");
+    }
+}
@@ -0,0 +1,418 @@
+//! Storage backends for the agent's state.
+
+use super::OAuth2Error;
+use async_trait::async_trait;
+use gloo_utils::window;
+use js_sys::{Function, Promise};
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+use wasm_bindgen::{JsCast, JsValue, closure::Closure};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+/// A key/value store, used by the agent to keep its state.
+///
+/// The agent uses a store for the transient state of a login flow (like the CSRF token and PKCE
+/// verifier), and optionally for persisting the session. All values are strings, encoded by
+/// the agent.
+#[async_trait(?Send)]
+pub trait StateStore: Debug {
+    /// Get a value, returns [`None`] if the key is not present.
+    async fn get(&self, key: &str) -> Result<Option<String>, OAuth2Error>;
+
+    /// Set a value, replacing any existing value.
+    async fn set(&self, key: &str, value: &str) -> Result<(), OAuth2Error>;
+
+    /// Delete a value, succeeds if the key is not present.
+    async fn delete(&self, key: &str) -> Result<(), OAuth2Error>;
+}
+
+/// Selection of a storage backend.
+///
+/// If the selected storage is not available (e.g. in some private browsing modes), the agent
+/// will fall back to [`StorageBackend::Memory`].
+///
+/// **NOTE:** The transient login state must survive navigating to the issuer and back for the
+/// default (redirect) login flow. Using [`StorageBackend::Memory`] for this only works with popup
+/// or silent logins.
+#[derive(Clone, Debug, Default)]
+pub enum StorageBackend {
+    /// Keep values in memory only.
+    Memory,
+    /// Use the browser's session storage.
+    #[default]
+    Session,
+    /// Use the browser's local storage.
+    Local,
+    /// Use an IndexedDB database of the provided name.
+    IndexedDb {
+        /// The name of the database.
+        database: String,
+    },
+    /// Use a custom store implementation.
+    Custom(Rc<dyn StateStore>),
+}
+
+impl PartialEq for StorageBackend {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Memory, Self::Memory)
+            | (Self::Session, Self::Session)
+            | (Self::Local, Self::Local) => true,
+            (Self::IndexedDb { database: a }, Self::IndexedDb { database: b }) => a == b,
+            (Self::Custom(a), Self::Custom(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl StorageBackend {
+    /// Open the store, prefixing all keys with the namespace, if any.
+    pub(crate) async fn open_namespaced(&self, namespace: Option<&str>) -> Rc<dyn StateStore> {
+        let store = self.open().await;
+        match namespace {
+            Some(namespace) => Rc::new(NamespacedStore::new(namespace, store)),
+            None => store,
+        }
+    }
+
+    /// Open the store, falling back to memory if the backend is not available.
+    pub(crate) async fn open(&self) -> Rc<dyn StateStore> {
+        let result: Result<Rc<dyn StateStore>, OAuth2Error> = match self {
+            Self::Memory => Ok(Rc::new(MemoryStore::default())),
+            Self::Session => WebStorageStore::session().map(|s| Rc::new(s) as _),
+            Self::Local => WebStorageStore::local().map(|s| Rc::new(s) as _),
+            Self::IndexedDb { database } => IndexedDbStore::open(database)
+                .await
+                .map(|s| Rc::new(s) as _),
+            Self::Custom(store) => Ok(store.clone()),
+        };
+
+        self.or_memory(result)
+    }
+
+    fn or_memory(&self, result: Result<Rc<dyn StateStore>, OAuth2Error>) -> Rc<dyn StateStore> {
+        result.unwrap_or_else(|err| {
+            log::warn!("Storage backend {self:?} is not available, falling back to memory: {err}");
+            Rc::new(MemoryStore::default())
+        })
+    }
+}
+
+/// A store, keeping values in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    values: Rc<RefCell<HashMap<String, String>>>,
+}
+
+#[async_trait(?Send)]
+impl StateStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, OAuth2Error> {
+        Ok(self.values.borrow().get(key).cloned())
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), OAuth2Error> {
+        self.values
+            .borrow_mut()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), OAuth2Error> {
+        self.values.borrow_mut().remove(key);
+        Ok(())
+    }
+}
+
+/// A store, prefixing all keys of another store with a namespace.
+///
+/// This allows multiple agents to share the same storage, without interfering with each other.
+#[derive(Clone, Debug)]
+pub struct NamespacedStore {
+    namespace: String,
+    store: Rc<dyn StateStore>,
+}
+
+impl NamespacedStore {
+    pub fn new(namespace: impl Into<String>, store: Rc<dyn StateStore>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            store,
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}/{key}", self.namespace)
+    }
+}
+
+#[async_trait(?Send)]
+impl StateStore for NamespacedStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, OAuth2Error> {
+        self.store.get(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), OAuth2Error> {
+        self.store.set(&self.key(key), value).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), OAuth2Error> {
+        self.store.delete(&self.key(key)).await
+    }
+}
+
+/// Key used to probe if a web storage is usable.
+const PROBE_KEY: &str = "ctron/oauth2/probe";
+
+/// A store, using the browser's session or local storage.
+#[derive(Clone, Debug)]
+pub struct WebStorageStore {
+    storage: web_sys::Storage,
+}
+
+impl WebStorageStore {
+    /// Use the browser's session storage.
+    pub fn session() -> Result<Self, OAuth2Error> {
+        Self::new(window().session_storage())
+    }
+
+    /// Use the browser's local storage.
+    pub fn local() -> Result<Self, OAuth2Error> {
+        Self::new(window().local_storage())
+    }
+
+    fn new(storage: Result<Option<web_sys::Storage>, JsValue>) -> Result<Self, OAuth2Error> {
+        let storage = storage
+            .ok()
+            .flatten()
+            .ok_or_else(|| OAuth2Error::storage("storage is not available".to_string()))?;
+
+        // some browsers provide the storage, but fail when writing to it
+        storage
+            .set_item(PROBE_KEY, "")
+            .and_then(|()| storage.remove_item(PROBE_KEY))
+            .map_err(|err| OAuth2Error::storage(js_error(err, "storage is not writable")))?;
+
+        Ok(Self { storage })
+    }
+
+    /// Get a value, without going through the async [`StateStore`] interface.
+    pub(crate) fn get_now(&self, key: &str) -> Result<Option<String>, OAuth2Error> {
+        self.storage
+            .get_item(key)
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to read value")))
+    }
+}
+
+#[async_trait(?Send)]
+impl StateStore for WebStorageStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, OAuth2Error> {
+        self.get_now(key)
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), OAuth2Error> {
+        self.storage
+            .set_item(key, value)
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to write value")))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), OAuth2Error> {
+        self.storage
+            .remove_item(key)
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to delete value")))
+    }
+}
+
+/// Name of the object store inside the IndexedDB database.
+const OBJECT_STORE: &str = "state";
+
+/// A store, using an IndexedDB database.
+#[derive(Clone, Debug)]
+pub struct IndexedDbStore {
+    db: IdbDatabase,
+}
+
+impl IndexedDbStore {
+    /// Open (and create if necessary) the database.
+    pub async fn open(database: &str) -> Result<Self, OAuth2Error> {
+        let factory = window()
+            .indexed_db()
+            .ok()
+            .flatten()
+            .ok_or_else(|| OAuth2Error::storage("IndexedDB is not available".to_string()))?;
+
+        let request = factory
+            .open_with_u32(database, 1)
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to open database")))?;
+
+        let upgrade = {
+            let request = request.clone();
+            Closure::once_into_js(move || {
+                if let Some(db) = request
+                    .result()
+                    .ok()
+                    .and_then(|db| db.dyn_into::<IdbDatabase>().ok())
+                {
+                    if let Err(err) = db.create_object_store(OBJECT_STORE) {
+                        log::warn!("Failed to create object store: {err:?}");
+                    }
+                }
+            })
+        };
+        request.set_onupgradeneeded(Some(upgrade.unchecked_ref()));
+
+        let db = complete(&request)
+            .await?
+            .dyn_into::<IdbDatabase>()
+            .map_err(|_| OAuth2Error::storage("unexpected database type".to_string()))?;
+
+        Ok(Self { db })
+    }
+
+    fn object_store(&self, mode: IdbTransactionMode) -> Result<IdbObjectStore, OAuth2Error> {
+        self.db
+            .transaction_with_str_and_mode(OBJECT_STORE, mode)
+            .and_then(|tx| tx.object_store(OBJECT_STORE))
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to start transaction")))
+    }
+}
+
+#[async_trait(?Send)]
+impl StateStore for IndexedDbStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, OAuth2Error> {
+        let request = self
+            .object_store(IdbTransactionMode::Readonly)?
+            .get(&JsValue::from_str(key))
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to read value")))?;
+
+        Ok(complete(&request).await?.as_string())
+    }
+
+    async fn set(&self, key: &str, value: &str) -> Result<(), OAuth2Error> {
+        let request = self
+            .object_store(IdbTransactionMode::Readwrite)?
+            .put_with_key(&JsValue::from_str(value), &JsValue::from_str(key))
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to write value")))?;
+
+        complete(&request).await.map(|_| ())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), OAuth2Error> {
+        let request = self
+            .object_store(IdbTransactionMode::Readwrite)?
+            .delete(&JsValue::from_str(key))
+            .map_err(|err| OAuth2Error::storage(js_error(err, "failed to delete value")))?;
+
+        complete(&request).await.map(|_| ())
+    }
+}
+
+/// Wait for an IndexedDB request to complete, returning its result.
+async fn complete(request: &IdbRequest) -> Result<JsValue, OAuth2Error> {
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let success = {
+            let request = request.clone();
+            Closure::once_into_js(move || {
+                let result = request.result().unwrap_or(JsValue::UNDEFINED);
+                let _ = resolve.call1(&JsValue::NULL, &result);
+            })
+        };
+        let error = Closure::once_into_js(move || {
+            let _ = reject.call0(&JsValue::NULL);
+        });
+        request.set_onsuccess(Some(success.unchecked_ref()));
+        request.set_onerror(Some(error.unchecked_ref()));
+    });
+
+    JsFuture::from(promise)
+        .await
+        .map_err(|_| OAuth2Error::storage("IndexedDB request failed".to_string()))
+}
+
+fn js_error(err: JsValue, default: &str) -> String {
+    err.as_string().unwrap_or_else(|| default.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn fallback_to_memory() {
+        let store = StorageBackend::Local.or_memory(Err(OAuth2Error::storage(
+            "storage is not available".to_string(),
+        )));
+
+        block_on(store.set("key", "value")).unwrap();
+        assert_eq!(
+            block_on(store.get("key")).unwrap().as_deref(),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn keep_available_store() {
+        let custom: Rc<dyn StateStore> = Rc::new(MemoryStore::default());
+        let store = StorageBackend::Local.or_memory(Ok(custom.clone()));
+        assert!(Rc::ptr_eq(&store, &custom));
+
+        let store = block_on(StorageBackend::Custom(custom.clone()).open());
+        assert!(Rc::ptr_eq(&store, &custom));
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryStore::default();
+        assert_eq!(block_on(store.get("key")).unwrap(), None);
+
+        block_on(store.set("key", "a")).unwrap();
+        block_on(store.set("key", "b")).unwrap();
+        assert_eq!(block_on(store.get("key")).unwrap().as_deref(), Some("b"));
+
+        block_on(store.delete("key")).unwrap();
+        block_on(store.delete("key")).unwrap();
+        assert_eq!(block_on(store.get("key")).unwrap(), None);
+    }
+
+    #[test]
+    fn namespaced_store() {
+        let memory = MemoryStore::default();
+        let first = NamespacedStore::new("first", Rc::new(memory.clone()));
+        let second = NamespacedStore::new("second", Rc::new(memory.clone()));
+
+        block_on(first.set("key", "a")).unwrap();
+        block_on(second.set("key", "b")).unwrap();
+
+        assert_eq!(block_on(first.get("key")).unwrap().as_deref(), Some("a"));
+        assert_eq!(block_on(second.get("key")).unwrap().as_deref(), Some("b"));
+        assert_eq!(
+            block_on(memory.get("first/key")).unwrap().as_deref(),
+            Some("a")
+        );
+        assert_eq!(block_on(memory.get("key")).unwrap(), None);
+
+        block_on(first.delete("key")).unwrap();
+        assert_eq!(block_on(first.get("key")).unwrap(), None);
+        assert_eq!(block_on(second.get("key")).unwrap().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn open_namespaced() {
+        let custom = MemoryStore::default();
+        let backend = StorageBackend::Custom(Rc::new(custom.clone()));
+
+        let store = block_on(backend.open_namespaced(Some("app")));
+        block_on(store.set("key", "value")).unwrap();
+        assert_eq!(
+            block_on(custom.get("app/key")).unwrap().as_deref(),
+            Some("value")
+        );
+
+        let store = block_on(backend.open_namespaced(None));
+        block_on(store.set("key", "value")).unwrap();
+        assert_eq!(
+            block_on(custom.get("key")).unwrap().as_deref(),
+            Some("value")
+        );
+    }
+}
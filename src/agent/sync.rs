@@ -0,0 +1,207 @@
+//! Synchronizing the session state between tabs of the same origin.
+
+use super::OAuth2Error;
+use crate::context::Authentication;
+use gloo_events::EventListener;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BroadcastChannel, MessageEvent};
+
+/// Prefix for the name of the broadcast channel, followed by the namespace and client ID.
+const CHANNEL_PREFIX: &str = "ctron/oauth2/sync/";
+
+/// A session event, shared with other tabs.
+///
+/// Tabs may run different versions of the application. Fields are added with a default, and
+/// messages of an unknown type are received as [`SyncMessage::Unknown`] and ignored.
+#[doc(hidden)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    bound(serialize = "S: Serialize", deserialize = "S: DeserializeOwned")
+)]
+pub enum SyncMessage<S> {
+    /// A tab logged in.
+    Login {
+        authentication: Authentication,
+        session_state: S,
+    },
+    /// A tab refreshed the token.
+    Refreshed {
+        authentication: Authentication,
+        session_state: S,
+    },
+    /// A tab logged out.
+    Logout,
+    /// The user was active in a tab.
+    Activity {
+        /// The time of the activity, in seconds since the epoch.
+        at: f64,
+    },
+    /// A message of a type this tab doesn't know, sent by a newer version.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A broadcast channel, connecting all tabs using the same client ID.
+pub(crate) struct TabSync {
+    channel: BroadcastChannel,
+    _listener: EventListener,
+}
+
+impl TabSync {
+    /// Join the channel for the namespace and client ID, delivering received messages to the
+    /// callback.
+    pub fn new<S, F>(
+        namespace: Option<&str>,
+        client_id: &str,
+        callback: F,
+    ) -> Result<Self, OAuth2Error>
+    where
+        S: Serialize + DeserializeOwned,
+        F: Fn(SyncMessage<S>) + 'static,
+    {
+        let name = match namespace {
+            Some(namespace) => format!("{CHANNEL_PREFIX}{namespace}/{client_id}"),
+            None => format!("{CHANNEL_PREFIX}{client_id}"),
+        };
+        let channel = BroadcastChannel::new(&name)
+            .map_err(|_| OAuth2Error::internal("unable to create broadcast channel".to_string()))?;
+
+        let listener = EventListener::new(&channel, "message", move |event| {
+            let Some(data) = event
+                .dyn_ref::<MessageEvent>()
+                .and_then(|event| event.data().as_string())
+            else {
+                return;
+            };
+            match serde_json::from_str(&data) {
+                Ok(msg) => callback(msg),
+                Err(err) => log::info!("Ignoring invalid sync message: {err}"),
+            }
+        });
+
+        Ok(Self {
+            channel,
+            _listener: listener,
+        })
+    }
+
+    /// Send a message to all other tabs.
+    pub fn send<S: Serialize>(&self, msg: &SyncMessage<S>) {
+        let data = match serde_json::to_string(msg) {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn!("Failed to encode sync message: {err}");
+                return;
+            }
+        };
+
+        if self
+            .channel
+            .post_message(&JsValue::from_str(&data))
+            .is_err()
+        {
+            log::warn!("Failed to send sync message");
+        }
+    }
+}
+
+impl Drop for TabSync {
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn authentication() -> Authentication {
+        Authentication {
+            access_token: "access".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires: Some(1_000),
+            scopes: Some(vec!["openid".to_string()]),
+            ..Default::default()
+        }
+    }
+
+    fn round_trip(msg: SyncMessage<String>) {
+        let data = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SyncMessage<String>>(&data).unwrap(),
+            msg
+        );
+    }
+
+    #[test]
+    fn round_trip_messages() {
+        round_trip(SyncMessage::Login {
+            authentication: authentication(),
+            session_state: "login".to_string(),
+        });
+        round_trip(SyncMessage::Refreshed {
+            authentication: authentication(),
+            session_state: "refreshed".to_string(),
+        });
+        round_trip(SyncMessage::Logout);
+        round_trip(SyncMessage::Activity {
+            at: 1_700_000_000.5,
+        });
+    }
+
+    #[test]
+    fn wire_format() {
+        assert_eq!(
+            serde_json::to_value(SyncMessage::<String>::Logout).unwrap(),
+            json!({"type": "logout"})
+        );
+        assert_eq!(
+            serde_json::to_value(SyncMessage::<String>::Activity { at: 1.5 }).unwrap(),
+            json!({"type": "activity", "at": 1.5})
+        );
+
+        let login = serde_json::to_value(SyncMessage::Refreshed {
+            authentication: authentication(),
+            session_state: "state".to_string(),
+        })
+        .unwrap();
+        assert_eq!(login["type"], "refreshed");
+        assert_eq!(login["authentication"]["access_token"], "access");
+        assert_eq!(login["session_state"], "state");
+    }
+
+    #[test]
+    fn unknown_type() {
+        let msg: SyncMessage<String> =
+            serde_json::from_value(json!({"type": "somethingNew", "value": 1})).unwrap();
+        assert_eq!(msg, SyncMessage::Unknown);
+    }
+
+    #[test]
+    fn older_and_newer_fields() {
+        // a message of a version without the optional fields, and with a field added later
+        let msg: SyncMessage<String> = serde_json::from_value(json!({
+            "type": "login",
+            "authentication": {
+                "access_token": "access",
+                "id_token": null,
+                "refresh_token": null,
+                "expires": 1000,
+                "added_later": true,
+            },
+            "session_state": "state",
+        }))
+        .unwrap();
+
+        let SyncMessage::Login { authentication, .. } = msg else {
+            panic!("unexpected message: {msg:?}");
+        };
+        assert_eq!(authentication.access_token, "access");
+        assert_eq!(authentication.expires, Some(1000));
+        assert_eq!(authentication.scopes, None);
+    }
+}
@@ -0,0 +1,43 @@
+//! Tracking tasks spawned by the agent, so that they can be cancelled.
+
+use futures::future::{AbortHandle, Abortable};
+use std::{cell::Cell, future::Future, rc::Rc};
+use wasm_bindgen_futures::spawn_local;
+
+/// Tasks spawned by the agent, which are aborted when this is dropped.
+#[derive(Default)]
+pub(crate) struct Tasks {
+    handles: Vec<(Rc<Cell<bool>>, AbortHandle)>,
+}
+
+impl Tasks {
+    /// Spawn a task, which will be aborted when the agent goes away.
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        // forget about tasks which already completed
+        self.handles.retain(|(done, _)| !done.get());
+
+        let (handle, registration) = AbortHandle::new_pair();
+        let done = Rc::new(Cell::new(false));
+
+        {
+            let done = done.clone();
+            spawn_local(async move {
+                let _ = Abortable::new(future, registration).await;
+                done.set(true);
+            });
+        }
+
+        self.handles.push((done, handle));
+    }
+}
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        for (_, handle) in self.handles.drain(..) {
+            handle.abort();
+        }
+    }
+}
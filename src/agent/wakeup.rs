@@ -0,0 +1,44 @@
+//! Detecting when the application resumes, after being throttled or suspended.
+
+use gloo_events::EventListener;
+use gloo_utils::{document, window};
+
+/// Listens for events indicating that timers might have been delayed.
+///
+/// Browsers throttle timers in background tabs, and pause them while the device is suspended.
+/// When the page becomes visible, gains focus, or is restored from the back/forward cache, the
+/// callback is invoked to re-evaluate the state.
+pub(crate) struct Wakeup {
+    _listeners: Vec<EventListener>,
+}
+
+impl Wakeup {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn() + Clone + 'static,
+    {
+        let mut listeners = Vec::with_capacity(3);
+
+        {
+            let callback = callback.clone();
+            listeners.push(EventListener::new(
+                &document(),
+                "visibilitychange",
+                move |_| {
+                    if !document().hidden() {
+                        callback();
+                    }
+                },
+            ));
+        }
+
+        for event in ["focus", "pageshow"] {
+            let callback = callback.clone();
+            listeners.push(EventListener::new(&window(), event, move |_| callback()));
+        }
+
+        Self {
+            _listeners: listeners,
+        }
+    }
+}
@@ -1,7 +1,13 @@
-use crate::agent::{self, Client};
+use crate::{
+    agent::{self, Client, OAuth2OperationsExt, SessionDeadlines},
+    context::OAuth2Context,
+};
+use std::any::Any;
 use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use yew::hook;
+use wasm_bindgen_futures::spawn_local;
+use yew::{Callback, hook};
 
 /// A wrapper for the [`agent::Agent`].
 ///
@@ -39,6 +45,129 @@ impl<C: Client> DerefMut for Agent<C> {
     }
 }
 
+/// Operations of the agent, which don't depend on the client type.
+///
+/// This allows components, which are not generic over the client type, to control the agent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgentControl {
+    reset: Callback<()>,
+    extend_session: Callback<()>,
+    step_up: Callback<Vec<String>>,
+    pub(crate) deadlines: SessionDeadlines,
+}
+
+impl AgentControl {
+    pub fn new<C: Client>(agent: agent::Agent<C>) -> Self {
+        let deadlines = agent.deadlines();
+        Self {
+            reset: {
+                let agent = agent.clone();
+                Callback::from(move |()| {
+                    if let Err(err) = agent.reset() {
+                        log::warn!("Failed to reset agent: {err}");
+                    }
+                })
+            },
+            step_up: {
+                let agent = agent.clone();
+                Callback::from(move |scopes| {
+                    let step_up = agent.step_up(scopes);
+                    spawn_local(async move {
+                        if let Err(err) = step_up.await {
+                            log::warn!("Failed to request additional scopes: {err}");
+                        }
+                    });
+                })
+            },
+            extend_session: Callback::from(move |()| {
+                let agent = agent.clone();
+                spawn_local(async move {
+                    if let Err(err) = agent.extend_session().await {
+                        log::warn!("Failed to extend session: {err}");
+                    }
+                });
+            }),
+            deadlines,
+        }
+    }
+
+    /// Reset the agent, see [`agent::OAuth2OperationsExt::reset`].
+    pub fn reset(&self) {
+        self.reset.emit(());
+    }
+
+    /// Request additional scopes, see [`agent::OAuth2OperationsExt::step_up`].
+    pub fn step_up(&self, scopes: Vec<String>) {
+        self.step_up.emit(scopes);
+    }
+
+    /// Extend the session, see [`agent::OAuth2OperationsExt::extend_session`].
+    pub fn extend_session(&self) {
+        self.extend_session.emit(());
+    }
+}
+
+/// The namespaced contexts, wrapping a component.
+///
+/// Each [`super::OAuth2`] component with a `namespace` adds itself, replacing an outer context
+/// of the same namespace. This allows accessing a context which is not the closest one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NamedContexts(Rc<Vec<NamedContext>>);
+
+#[derive(Clone, Debug)]
+struct NamedContext {
+    namespace: String,
+    id: usize,
+    agent: Rc<dyn Any>,
+    state: OAuth2Context,
+}
+
+impl PartialEq for NamedContext {
+    fn eq(&self, other: &Self) -> bool {
+        self.namespace == other.namespace && self.id == other.id && self.state == other.state
+    }
+}
+
+impl NamedContexts {
+    /// Add or replace the context of a namespace.
+    pub(crate) fn with<C: Client>(
+        &self,
+        namespace: &str,
+        agent: &Agent<C>,
+        state: OAuth2Context,
+    ) -> Self {
+        let mut contexts: Vec<_> = self
+            .0
+            .iter()
+            .filter(|context| context.namespace != namespace)
+            .cloned()
+            .collect();
+        contexts.push(NamedContext {
+            namespace: namespace.to_string(),
+            id: agent.1,
+            agent: Rc::new(agent.clone()),
+            state,
+        });
+        Self(Rc::new(contexts))
+    }
+
+    fn get(&self, namespace: &str) -> Option<&NamedContext> {
+        self.0.iter().find(|context| context.namespace == namespace)
+    }
+
+    /// Get the agent of a namespace, if it uses the client type `C`.
+    pub fn agent<C: Client>(&self, namespace: &str) -> Option<Agent<C>> {
+        self.get(namespace)
+            .and_then(|context| context.agent.downcast_ref::<Agent<C>>())
+            .cloned()
+    }
+
+    /// Get the authentication state of a namespace.
+    pub fn state(&self, namespace: &str) -> Option<OAuth2Context> {
+        self.get(namespace).map(|context| context.state.clone())
+    }
+}
+
 /// Get the authentication agent.
 #[hook]
 pub fn use_auth_agent<C>() -> Option<Agent<C>>
@@ -47,3 +176,12 @@ where
 {
     yew::prelude::use_context()
 }
+
+/// Get the authentication agent of the context with the provided namespace.
+#[hook]
+pub fn use_named_auth_agent<C>(namespace: &str) -> Option<Agent<C>>
+where
+    C: Client,
+{
+    yew::prelude::use_context::<NamedContexts>().and_then(|contexts| contexts.agent::<C>(namespace))
+}
@@ -5,11 +5,16 @@ mod agent;
 pub use agent::*;
 
 use crate::{
-    agent::{AgentConfiguration, Client, LoginOptions, LogoutOptions, OAuth2Operations},
+    agent::{
+        AgentConfiguration, AuthEvent, AuthEvents, Client, EventSubscription, LoginOptions,
+        LogoutOptions, OAuth2Operations, RefreshPolicy, RetryOptions, SilentLoginOptions,
+        StorageBackend, same_policy,
+    },
     context::{LatestAccessToken, OAuth2Context},
 };
 use agent::Agent as AgentContext;
-use std::time::Duration;
+use std::{rc::Rc, time::Duration};
+use yew::context::ContextHandle;
 use yew::prelude::*;
 
 /// Properties for the context component.
@@ -18,6 +23,23 @@ pub struct OAuth2Properties<C: Client> {
     /// The client configuration
     pub config: C::Configuration,
 
+    /// A namespace, separating this context from other contexts of the same origin.
+    ///
+    /// The namespace prefixes all storage keys and tags the login requests, so that multiple
+    /// contexts (e.g. for different issuers) can be used on the same page, or by different
+    /// applications of the same origin. Hooks like [`use_named_auth_agent`] can target the
+    /// context by its namespace.
+    ///
+    /// The `state` of a login request is tagged as `<namespace>.<random>`, and a context only
+    /// processes authorization responses carrying its own tag:
+    ///
+    /// * A context without a namespace ignores any response whose `state` contains a `.`, as it
+    ///   is considered to be tagged.
+    /// * A response without a `state` (e.g. some error responses) can't be attributed, and is
+    ///   only processed by a context without a namespace. Other contexts leave it in the URL.
+    #[prop_or_default]
+    pub namespace: Option<String>,
+
     /// Scopes to request for the session
     #[prop_or_default]
     pub scopes: Vec<String>,
@@ -28,11 +50,18 @@ pub struct OAuth2Properties<C: Client> {
     #[prop_or(Duration::from_secs(30))]
     pub grace_period: Duration,
 
+    /// A policy deciding when to refresh the token, overriding the `grace_period`.
+    ///
+    /// See [`RefreshPolicy`] for more information. The policy is compared by its instance, so
+    /// it should be kept stable across renders (e.g. using `use_memo`).
+    #[prop_or_default]
+    pub refresh_policy: Option<Rc<dyn RefreshPolicy>>,
+
     /// A maximum expiration time.
     ///
     /// This can be used to limit the token timeout. If present, the token will be considered
-    /// expired at the provided expiration or the configured maximum expiration, whatever is
-    /// first.
+    /// expired at the provided expiration or the configured maximum expiration after the token
+    /// was issued, whatever is first.
     #[prop_or_default]
     pub max_expiration: Option<Duration>,
 
@@ -51,15 +80,86 @@ pub struct OAuth2Properties<C: Client> {
     /// Default [`LogoutOptions`] that will be used unless more specific options have been requested.
     #[prop_or_default]
     pub logout_options: Option<LogoutOptions>,
+
+    /// Enable silent login, restoring and renewing sessions using a hidden iframe.
+    ///
+    /// See [`SilentLoginOptions`] for more information.
+    #[prop_or_default]
+    pub silent_login: Option<SilentLoginOptions>,
+
+    /// The storage used for the transient state of the login process.
+    #[prop_or_default]
+    pub state_storage: StorageBackend,
+
+    /// Persist the session, including refresh tokens, in the provided storage.
+    ///
+    /// By default, tokens are only held in memory, and a page reload requires a new login (which
+    /// might happen without user interaction). Enabling this allows resuming the session after
+    /// a reload, but makes the tokens accessible to anything able to read the storage.
+    #[prop_or_default]
+    pub token_storage: Option<StorageBackend>,
+
+    /// Synchronize the session between tabs of the same origin and client ID.
+    ///
+    /// When enabled, a login, token refresh, or logout in one tab is applied to all other tabs.
+    #[prop_or_default]
+    pub sync_tabs: bool,
+
+    /// Retry a token refresh which failed with a temporary error.
+    #[prop_or_default]
+    pub refresh_retry: RetryOptions,
+
+    /// Start a new login when the requested scopes grow, to let the user consent to them.
+    ///
+    /// By default, the current session is kept, and additional scopes are only requested
+    /// with the next login.
+    #[prop_or_default]
+    pub reconsent: bool,
+
+    /// End the session after the user was inactive for this duration.
+    ///
+    /// User activity (like pointer or keyboard input) is shared between tabs, if `sync_tabs` is
+    /// enabled. The session ends with [`crate::context::Reason::IdleTimeout`].
+    #[prop_or_default]
+    pub idle_timeout: Option<Duration>,
+
+    /// End the session after this duration, no matter how often the token gets refreshed.
+    ///
+    /// The session ends with [`crate::context::Reason::SessionLimit`].
+    #[prop_or_default]
+    pub session_limit: Option<Duration>,
+
+    /// Emit [`AuthEvent::SessionEnding`] this amount of time before the idle timeout or session
+    /// limit ends the session.
+    #[prop_or_default]
+    pub session_warning: Option<Duration>,
+
+    /// Called for every [`AuthEvent`] of the agent.
+    #[prop_or_default]
+    pub on_event: Option<Callback<AuthEvent>>,
 }
 
 impl<C: Client> PartialEq for OAuth2Properties<C> {
     fn eq(&self, other: &Self) -> bool {
         self.config == other.config
+            && self.namespace == other.namespace
             && self.scopes == other.scopes
             && self.grace_period == other.grace_period
+            && same_policy(&self.refresh_policy, &other.refresh_policy)
             && self.max_expiration == other.max_expiration
             && self.audience == other.audience
+            && self.login_options == other.login_options
+            && self.logout_options == other.logout_options
+            && self.silent_login == other.silent_login
+            && self.state_storage == other.state_storage
+            && self.token_storage == other.token_storage
+            && self.sync_tabs == other.sync_tabs
+            && self.refresh_retry == other.refresh_retry
+            && self.reconsent == other.reconsent
+            && self.idle_timeout == other.idle_timeout
+            && self.session_limit == other.session_limit
+            && self.session_warning == other.session_warning
+            && self.on_event == other.on_event
             && self.children == other.children
     }
 }
@@ -71,12 +171,18 @@ pub struct OAuth2<C: Client> {
     context: OAuth2Context,
     latest_access_token: LatestAccessToken,
     agent: AgentContext<C>,
+    control: AgentControl,
     config: AgentConfiguration<C>,
+    named: NamedContexts,
+    _subscription: EventSubscription,
+    _named: Option<ContextHandle<NamedContexts>>,
 }
 
 #[doc(hidden)]
 pub enum Msg {
     Context(OAuth2Context),
+    Event(AuthEvent),
+    Named(NamedContexts),
 }
 
 impl<C: Client> Component for OAuth2<C> {
@@ -88,28 +194,52 @@ impl<C: Client> Component for OAuth2<C> {
         let callback = ctx.link().callback(Msg::Context);
 
         let agent = crate::agent::Agent::new(move |s| callback.emit(s));
+        // subscribe before configuring, so that no event is missed
+        let subscription = agent.subscribe(ctx.link().callback(Msg::Event));
         let _ = agent.configure(config.clone());
 
+        // the named contexts of outer components
+        let (named, named_handle) = ctx.link().context(ctx.link().callback(Msg::Named)).unzip();
+
         Self {
             context: OAuth2Context::NotInitialized,
             latest_access_token: LatestAccessToken {
                 access_token: Default::default(),
+                dpop: Default::default(),
             },
+            control: AgentControl::new(agent.clone()),
             agent: AgentContext::new(agent),
             config,
+            named: named.unwrap_or_default(),
+            _subscription: subscription,
+            _named: named_handle,
         }
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Self::Message::Context(context) => {
                 if self.context != context {
                     self.latest_access_token
                         .set_access_token(context.access_token());
+                    self.latest_access_token
+                        .set_dpop(context.authentication().and_then(|auth| auth.dpop.clone()));
                     self.context = context;
                     return true;
                 }
             }
+            Self::Message::Event(event) => {
+                if matches!(event, AuthEvent::TokenExpired) {
+                    self.latest_access_token.set_access_token(None::<String>);
+                }
+                if let Some(on_event) = &ctx.props().on_event {
+                    on_event.emit(event);
+                }
+            }
+            Self::Message::Named(named) => {
+                self.named = named;
+                return true;
+            }
         }
         false
     }
@@ -126,17 +256,40 @@ impl<C: Client> Component for OAuth2<C> {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        html!(
+        let content = html!(
             <>
                 <ContextProvider<OAuth2Context> context={self.context.clone()} >
                     <ContextProvider<AgentContext<C>> context={self.agent.clone()}>
-                        <ContextProvider<LatestAccessToken> context={self.latest_access_token.clone()}>
-                            { for ctx.props().children.iter() }
-                        </ContextProvider<LatestAccessToken>>
+                        <ContextProvider<AgentControl> context={self.control.clone()}>
+                            <ContextProvider<AuthEvents> context={self.agent.events()}>
+                                <ContextProvider<LatestAccessToken> context={self.latest_access_token.clone()}>
+                                    { for ctx.props().children.iter() }
+                                </ContextProvider<LatestAccessToken>>
+                            </ContextProvider<AuthEvents>>
+                        </ContextProvider<AgentControl>>
                     </ContextProvider<AgentContext<C>>>
                 </ContextProvider<OAuth2Context>>
             </>
-        )
+        );
+
+        match &ctx.props().namespace {
+            Some(namespace) => {
+                let named = self
+                    .named
+                    .with(namespace, &self.agent, self.context.clone());
+                html!(
+                    <ContextProvider<NamedContexts> context={named}>
+                        { content }
+                    </ContextProvider<NamedContexts>>
+                )
+            }
+            None => content,
+        }
+    }
+
+    fn destroy(&mut self, _: &Context<Self>) {
+        // stop timers and requests of the agent, other handles might still be around
+        self.agent.shutdown();
     }
 }
 
@@ -144,12 +297,23 @@ impl<C: Client> OAuth2<C> {
     fn make_config(props: &OAuth2Properties<C>) -> AgentConfiguration<C> {
         AgentConfiguration {
             config: props.config.clone(),
+            namespace: props.namespace.clone(),
             scopes: props.scopes.clone(),
             grace_period: props.grace_period,
+            refresh_policy: props.refresh_policy.clone(),
             max_expiration: props.max_expiration,
             audience: props.audience.clone(),
             default_login_options: props.login_options.clone(),
             default_logout_options: props.logout_options.clone(),
+            silent_login: props.silent_login.clone(),
+            state_storage: props.state_storage.clone(),
+            token_storage: props.token_storage.clone(),
+            sync_tabs: props.sync_tabs,
+            refresh_retry: props.refresh_retry.clone(),
+            reconsent: props.reconsent,
+            idle_timeout: props.idle_timeout,
+            session_limit: props.session_limit,
+            session_warning: props.session_warning,
         }
     }
 }
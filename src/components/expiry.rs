@@ -0,0 +1,87 @@
+//! The [`SessionExpiryWarning`] component
+
+use crate::{components::context::AgentControl, context::Reason, hook::use_session_expiry};
+use std::time::Duration;
+use yew::prelude::*;
+
+/// Properties for the [`SessionExpiryWarning`] component
+#[derive(Clone, Debug, PartialEq, Properties)]
+pub struct SessionExpiryWarningProps {
+    #[prop_or_default]
+    pub id: Option<String>,
+    #[prop_or_default]
+    pub style: Option<String>,
+    #[prop_or_default]
+    pub class: Option<String>,
+    #[prop_or_default]
+    pub element: Option<String>,
+    /// Render the warning once the remaining time is below this.
+    #[prop_or(Duration::from_secs(120))]
+    pub within: Duration,
+    /// The cadence of updating the remaining time.
+    #[prop_or(Duration::from_secs(1))]
+    pub cadence: Duration,
+    /// The label of the button, extending the session.
+    #[prop_or_else(|| "Continue".to_string())]
+    pub extend: String,
+    /// Content to render instead of the default message.
+    #[prop_or_default]
+    pub children: Children,
+}
+
+/// Yew component, rendering a warning when the session is about to end.
+///
+/// The warning contains a button, extending the session (see
+/// [`crate::agent::OAuth2OperationsExt::extend_session`]). As a session limit can't be extended,
+/// the button is not shown in that case.
+///
+/// ```rust
+/// use yew::prelude::*;
+/// use yew_oauth2::prelude::*;
+///
+/// #[function_component(Warning)]
+/// fn warning() -> Html {
+///   html!(
+///     <SessionExpiryWarning extend="Keep me logged in" />
+///   )
+/// }
+/// ```
+#[function_component(SessionExpiryWarning)]
+pub fn session_expiry_warning(props: &SessionExpiryWarningProps) -> Html {
+    let expiry = use_session_expiry(props.cadence);
+    let control = use_context::<AgentControl>();
+
+    let element = props.element.as_deref().unwrap_or("div").to_string();
+
+    let onclick = use_callback(control, |_: MouseEvent, control| {
+        if let Some(control) = control {
+            control.extend_session();
+        }
+    });
+
+    let Some((remaining, reason)) = expiry.and_then(|expiry| expiry.remaining()) else {
+        return html!();
+    };
+    if remaining > props.within {
+        return html!();
+    }
+
+    let secs = remaining.as_secs();
+    let content = match props.children.is_empty() {
+        true => html!({ format!("Your session expires in {}:{:02}.", secs / 60, secs % 60) }),
+        false => html!({ for props.children.iter() }),
+    };
+
+    html!(
+        <@{element}
+            id={ props.id.clone() }
+            style={ props.style.clone() }
+            class={ &props.class }
+            >
+            { content }
+            if reason != Reason::SessionLimit {
+                <button {onclick}>{ &props.extend }</button>
+            }
+        </@>
+    )
+}
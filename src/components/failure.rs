@@ -1,7 +1,11 @@
 //! The [`Failure`] component
 
 use super::missing_context;
-use crate::context::OAuth2Context;
+use crate::{
+    agent::{ErrorKind, OAuth2Error},
+    components::context::AgentControl,
+    context::OAuth2Context,
+};
 use yew::prelude::*;
 
 /// Properties for the [`Failure`] component
@@ -15,19 +19,60 @@ pub struct FailureProps {
     pub class: Option<String>,
     #[prop_or_default]
     pub element: Option<String>,
+    /// Only render for failures of these kinds. If empty, render for all failures.
+    #[prop_or_default]
+    pub kinds: Vec<ErrorKind>,
+    /// Don't render for failures of these kinds.
+    #[prop_or_default]
+    pub exclude: Vec<ErrorKind>,
+    /// Render a "try again" button with this label, resetting the agent when clicked.
+    #[prop_or_default]
+    pub try_again: Option<String>,
     #[prop_or_default]
     pub children: Children,
 }
 
+/// Yew component, rendering children when the agent failed.
+///
+/// Using the `kinds` and `exclude` properties, it is possible to render different content,
+/// depending on the kind of failure:
+///
+/// ```rust
+/// use yew::prelude::*;
+/// use yew_oauth2::prelude::*;
+/// use yew_oauth2::agent::ErrorKind;
+///
+/// #[function_component(LoginFailure)]
+/// fn login_failure() -> Html {
+///   let down = vec![ErrorKind::Network, ErrorKind::Unavailable];
+///   html!(
+///     <>
+///       <Failure kinds={vec![ErrorKind::AccessDenied]}>{ "You cancelled the login." }</Failure>
+///       <Failure kinds={down.clone()}>{ "The login service is currently not available." }</Failure>
+///       <Failure exclude={[vec![ErrorKind::AccessDenied], down].concat()}><FailureMessage/></Failure>
+///     </>
+///   )
+/// }
+/// ```
 #[function_component(Failure)]
 pub fn failure(props: &FailureProps) -> Html {
     let auth = use_context::<OAuth2Context>();
+    let control = use_context::<AgentControl>();
 
     let element = props.element.as_deref().unwrap_or("div").to_string();
 
+    let onclick = use_callback(control, |_: MouseEvent, control| {
+        if let Some(control) = control {
+            control.reset();
+        }
+    });
+
     match auth {
         None => missing_context(),
-        Some(OAuth2Context::Failed(..)) => {
+        Some(OAuth2Context::Failed(err))
+            if (props.kinds.is_empty() || props.kinds.contains(&err.kind))
+                && !props.exclude.contains(&err.kind) =>
+        {
             html!(
                 <@{element}
                     id={ props.id.clone() }
@@ -35,6 +80,9 @@ pub fn failure(props: &FailureProps) -> Html {
                     class={ &props.class }
                     >
                     { for props.children.iter() }
+                    if let Some(label) = &props.try_again {
+                        <button {onclick}>{ label }</button>
+                    }
                 </@>
             )
         }
@@ -42,7 +90,7 @@ pub fn failure(props: &FailureProps) -> Html {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Properties)]
+#[derive(Clone, Debug, PartialEq, Properties)]
 pub struct FailureMessageProps {
     #[prop_or_default]
     pub id: Option<String>,
@@ -52,6 +100,9 @@ pub struct FailureMessageProps {
     pub class: Option<String>,
     #[prop_or_default]
     pub element: Option<String>,
+    /// Render the content for the error, instead of the default message.
+    #[prop_or_default]
+    pub render: Option<Callback<OAuth2Error, Html>>,
 }
 
 #[function_component(FailureMessage)]
@@ -62,14 +113,18 @@ pub fn failure_message(props: &FailureMessageProps) -> Html {
 
     match auth {
         None => missing_context(),
-        Some(OAuth2Context::Failed(message)) => {
+        Some(OAuth2Context::Failed(err)) => {
+            let content = match &props.render {
+                Some(render) => render.emit(err),
+                None => html!({ err.to_string() }),
+            };
             html!(
                 <@{element}
                     id={ props.id.clone() }
                     style={ props.style.clone() }
                     class={ &props.class }
                     >
-                    { message }
+                    { content }
                 </@>
             )
         }
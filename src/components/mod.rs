@@ -2,16 +2,20 @@
 
 pub mod authenticated;
 pub mod context;
+pub mod expiry;
 pub mod failure;
 pub mod noauth;
 pub mod redirect;
+pub mod scopes;
 pub mod use_authentication;
 
 // only put pub use for common components
 
 pub use authenticated::*;
+pub use expiry::*;
 pub use failure::*;
 pub use noauth::*;
+pub use scopes::*;
 pub use use_authentication::*;
 
 use yew::prelude::*;
@@ -135,7 +135,7 @@ where
                         let _ = agent.start_login();
                     }
                 }
-                Reason::Expired | Reason::Logout => {
+                Reason::Expired | Reason::Logout | Reason::IdleTimeout | Reason::SessionLimit => {
                     match self.auth {
                         None | Some(OAuth2Context::NotInitialized) => {
                             if let Some(agent) = &mut self.agent {
@@ -0,0 +1,81 @@
+//! The [`RequireScopes`] component
+
+use super::missing_context;
+use crate::{components::context::AgentControl, context::OAuth2Context};
+use yew::prelude::*;
+
+/// Properties for the [`RequireScopes`] component
+#[derive(Clone, Debug, PartialEq, Properties)]
+pub struct RequireScopesProps {
+    /// The scopes required to render the children.
+    pub scopes: Vec<String>,
+    /// Request the missing scopes, by starting a new login.
+    ///
+    /// See [`crate::agent::OAuth2OperationsExt::step_up`].
+    #[prop_or_default]
+    pub step_up: bool,
+    /// Content to render while the token lacks the scopes.
+    #[prop_or_default]
+    pub fallback: Html,
+    #[prop_or_default]
+    pub children: Children,
+}
+
+/// Yew component, rendering children only when the token was granted the required scopes.
+///
+/// If the token lacks any of the scopes, the fallback is rendered instead. If the granted scopes
+/// of the session are unknown, they are considered missing.
+///
+/// ```rust
+/// use yew::prelude::*;
+/// use yew_oauth2::prelude::*;
+///
+/// #[function_component(Calendar)]
+/// fn calendar() -> Html {
+///   html!(
+///     <RequireScopes
+///         scopes={vec!["calendar.write".to_string()]}
+///         step_up=true
+///         fallback={html!({ "Requesting access to your calendar…" })}
+///     >
+///       { "Edit your calendar" }
+///     </RequireScopes>
+///   )
+/// }
+/// ```
+#[function_component(RequireScopes)]
+pub fn require_scopes(props: &RequireScopesProps) -> Html {
+    let auth = use_context::<OAuth2Context>();
+    let control = use_context::<AgentControl>();
+
+    let missing = match &auth {
+        Some(OAuth2Context::Authenticated(auth)) => {
+            let granted = auth.scopes.as_deref().unwrap_or_default();
+            props
+                .scopes
+                .iter()
+                .filter(|scope| !granted.contains(scope))
+                .cloned()
+                .collect()
+        }
+        _ => vec![],
+    };
+
+    use_effect_with(
+        (missing.clone(), props.step_up, control),
+        |(missing, step_up, control)| {
+            if let (true, Some(control)) = (*step_up && !missing.is_empty(), control) {
+                control.step_up(missing.clone());
+            }
+        },
+    );
+
+    match auth {
+        None => missing_context(),
+        Some(OAuth2Context::Authenticated(_)) if missing.is_empty() => {
+            html!({ for props.children.iter() })
+        }
+        Some(OAuth2Context::Authenticated(_)) => props.fallback.clone(),
+        Some(_) => html!(),
+    }
+}
@@ -2,6 +2,49 @@
 
 use serde::{Deserialize, Serialize};
 
+/// How the issuer returns the authorization response to the redirect URL.
+///
+/// Independent of the requested mode, the agent accepts responses in the query, the fragment,
+/// or the query of a hash-based route (like `#/callback?code=...`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseMode {
+    /// In the query of the URL (`?code=...`), the default for the authorization code flow.
+    #[default]
+    Query,
+    /// In the fragment of the URL (`#code=...`).
+    Fragment,
+}
+
+impl ResponseMode {
+    /// The value of the `response_mode` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Query => "query",
+            Self::Fragment => "fragment",
+        }
+    }
+}
+
+/// The use of pushed authorization requests (PAR, RFC 9126).
+///
+/// When used, the parameters of the authorization request (including the query parameters of
+/// the [`crate::agent::LoginOptions`]) are sent to the issuer directly, and the browser only
+/// navigates to the issuer with the returned `request_uri`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PushedAuthorization {
+    /// Send the authorization request as part of the URL.
+    #[default]
+    Disabled,
+    /// Push the authorization request if the issuer supports it, otherwise send it as part of
+    /// the URL.
+    Preferred,
+    /// Push the authorization request, failing the configuration if the issuer doesn't
+    /// support it.
+    Required,
+}
+
 /// Configuration for OpenID Connect
 pub mod openid {
     use super::*;
@@ -57,6 +100,24 @@ pub mod openid {
         pub additional_trusted_audiences: Vec<String>,
         /// Specifies whether the issuer claim must match the expected issuer URL for the provider.
         pub require_issuer_match: bool,
+        /// The response mode to request, unless overridden by the login options.
+        ///
+        /// If not set, no `response_mode` is requested, using the default of the issuer.
+        pub response_mode: Option<ResponseMode>,
+        /// The use of pushed authorization requests.
+        #[serde(default)]
+        pub pushed_authorization: PushedAuthorization,
+        /// An override for the pushed authorization request endpoint.
+        ///
+        /// By default, the `pushed_authorization_request_endpoint` of the discovery metadata
+        /// is used.
+        pub pushed_authorization_url: Option<String>,
+        /// Bind the tokens to a key, using DPoP (RFC 9449).
+        ///
+        /// As the key only lives as long as the page, the session is neither persisted nor
+        /// synchronized between tabs. See [`crate::agent::DpopKey`] for more information.
+        #[serde(default)]
+        pub dpop: bool,
     }
 
     impl Config {
@@ -73,6 +134,10 @@ pub mod openid {
                 post_logout_redirect_name: None,
                 additional_trusted_audiences: vec![],
                 require_issuer_match: true,
+                response_mode: None,
+                pushed_authorization: PushedAuthorization::Disabled,
+                pushed_authorization_url: None,
+                dpop: false,
             }
         }
 
@@ -140,6 +205,36 @@ pub mod openid {
             self.metadata_source = metadata_source;
             self
         }
+
+        /// Set the response mode
+        pub fn with_response_mode(mut self, response_mode: ResponseMode) -> Self {
+            self.response_mode = Some(response_mode);
+            self
+        }
+
+        /// Set the use of pushed authorization requests
+        pub fn with_pushed_authorization(
+            mut self,
+            pushed_authorization: PushedAuthorization,
+        ) -> Self {
+            self.pushed_authorization = pushed_authorization;
+            self
+        }
+
+        /// Set an override for the pushed authorization request endpoint.
+        pub fn with_pushed_authorization_url(
+            mut self,
+            pushed_authorization_url: impl Into<String>,
+        ) -> Self {
+            self.pushed_authorization_url = Some(pushed_authorization_url.into());
+            self
+        }
+
+        /// Enable binding the tokens to a key, using DPoP
+        pub fn with_dpop(mut self, dpop: bool) -> Self {
+            self.dpop = dpop;
+            self
+        }
     }
 }
 
@@ -162,6 +257,21 @@ pub mod oauth2 {
         pub auth_url: String,
         /// The token exchange URL
         pub token_url: String,
+        /// The response mode to request, unless overridden by the login options.
+        ///
+        /// If not set, no `response_mode` is requested, using the default of the issuer.
+        pub response_mode: Option<ResponseMode>,
+        /// The use of pushed authorization requests.
+        #[serde(default)]
+        pub pushed_authorization: PushedAuthorization,
+        /// The pushed authorization request endpoint.
+        pub pushed_authorization_url: Option<String>,
+        /// Bind the tokens to a key, using DPoP (RFC 9449).
+        ///
+        /// As the key only lives as long as the page, the session is neither persisted nor
+        /// synchronized between tabs. See [`crate::agent::DpopKey`] for more information.
+        #[serde(default)]
+        pub dpop: bool,
     }
 
     impl Config {
@@ -175,7 +285,41 @@ pub mod oauth2 {
                 client_id: client_id.into(),
                 auth_url: auth_url.into(),
                 token_url: token_url.into(),
+                response_mode: None,
+                pushed_authorization: PushedAuthorization::Disabled,
+                pushed_authorization_url: None,
+                dpop: false,
             }
         }
+
+        /// Set the response mode
+        pub fn with_response_mode(mut self, response_mode: ResponseMode) -> Self {
+            self.response_mode = Some(response_mode);
+            self
+        }
+
+        /// Set the use of pushed authorization requests
+        pub fn with_pushed_authorization(
+            mut self,
+            pushed_authorization: PushedAuthorization,
+        ) -> Self {
+            self.pushed_authorization = pushed_authorization;
+            self
+        }
+
+        /// Set the pushed authorization request endpoint
+        pub fn with_pushed_authorization_url(
+            mut self,
+            pushed_authorization_url: impl Into<String>,
+        ) -> Self {
+            self.pushed_authorization_url = Some(pushed_authorization_url.into());
+            self
+        }
+
+        /// Enable binding the tokens to a key, using DPoP
+        pub fn with_dpop(mut self, dpop: bool) -> Self {
+            self.dpop = dpop;
+            self
+        }
     }
 }
@@ -2,6 +2,8 @@
 
 mod utils;
 
+use crate::agent::{AuthorizationDetail, DPOP_HEADER, DpopKey, OAuth2Error};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::rc::Rc;
 pub use utils::*;
@@ -13,7 +15,11 @@ pub type Claims = openidconnect::IdTokenClaims<
 >;
 
 /// The authentication information
-#[derive(Clone, Debug, Default, PartialEq)]
+///
+///**NOTE**: This is a non-exhaustive struct, created by the agent. For a placeholder, use
+/// [`Authentication::default`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[cfg_attr(not(feature = "openid"), derive(Eq))]
 pub struct Authentication {
     /// The access token
@@ -27,6 +33,70 @@ pub struct Authentication {
     pub claims: Option<Rc<Claims>>,
     /// Expiration timestamp in seconds
     pub expires: Option<u64>,
+    /// The scopes granted to the token, if known
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    /// The authorization details granted to the token, if known
+    #[serde(default)]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
+    /// Expiration timestamp of the refresh token in seconds, if known
+    #[serde(default)]
+    pub refresh_expires: Option<u64>,
+    /// Timestamp in seconds, when the token was issued
+    #[serde(default)]
+    pub issued: Option<u64>,
+    /// Timestamp in seconds, when the session started
+    ///
+    /// This is set by the agent, and kept when the token gets refreshed.
+    #[serde(default)]
+    pub session_start: Option<u64>,
+    /// The key the tokens are bound to, if DPoP is enabled
+    ///
+    /// The key can't leave the page, so it is not serialized. Sessions bound to a key are neither
+    /// persisted nor synchronized between tabs.
+    #[serde(skip)]
+    pub dpop: Option<DpopKey>,
+}
+
+impl Authentication {
+    /// The scheme to use in the `Authorization` header: `DPoP` if the token is bound to a key,
+    /// `Bearer` otherwise.
+    pub fn token_type(&self) -> &'static str {
+        match self.dpop {
+            Some(_) => "DPoP",
+            None => "Bearer",
+        }
+    }
+
+    /// Create the headers for a request to a resource server.
+    ///
+    /// This returns the `Authorization` header, and, if the token is bound to a key, the `DPoP`
+    /// header with a proof for the method and URL of the request.
+    pub async fn request_headers(
+        &self,
+        method: &str,
+        url: &str,
+    ) -> Result<Vec<(&'static str, String)>, OAuth2Error> {
+        request_headers(&self.access_token, self.dpop.as_ref(), method, url).await
+    }
+}
+
+async fn request_headers(
+    access_token: &str,
+    dpop: Option<&DpopKey>,
+    method: &str,
+    url: &str,
+) -> Result<Vec<(&'static str, String)>, OAuth2Error> {
+    Ok(match dpop {
+        Some(key) => vec![
+            ("Authorization", format!("DPoP {access_token}")),
+            (
+                DPOP_HEADER,
+                key.proof(method, url, Some(access_token)).await?,
+            ),
+        ],
+        None => vec![("Authorization", format!("Bearer {access_token}"))],
+    })
 }
 
 /// The authentication context
@@ -43,7 +113,7 @@ pub enum OAuth2Context {
     /// Session is authenticated.
     Authenticated(Authentication),
     /// Something failed.
-    Failed(String),
+    Failed(OAuth2Error),
 }
 
 impl OAuth2Context {
@@ -86,12 +156,17 @@ pub enum Reason {
     Expired,
     /// Because the user chose to log out.
     Logout,
+    /// Because the user was inactive for too long.
+    IdleTimeout,
+    /// Because the session exceeded its maximum lifetime.
+    SessionLimit,
 }
 
 /// A handle to access the latest access token.
 #[derive(Clone)]
 pub struct LatestAccessToken {
     pub(crate) access_token: Rc<RefCell<Option<String>>>,
+    pub(crate) dpop: Rc<RefCell<Option<DpopKey>>>,
 }
 
 impl PartialEq for LatestAccessToken {
@@ -109,7 +184,28 @@ impl LatestAccessToken {
         }
     }
 
+    /// Create the headers for a request to a resource server, using the latest access token.
+    ///
+    /// See [`Authentication::request_headers`]. Returns [`None`] if there is no access token.
+    pub async fn request_headers(
+        &self,
+        method: &str,
+        url: &str,
+    ) -> Result<Option<Vec<(&'static str, String)>>, OAuth2Error> {
+        let Some(access_token) = self.access_token() else {
+            return Ok(None);
+        };
+        let dpop = self.dpop.borrow().clone();
+        request_headers(&access_token, dpop.as_ref(), method, url)
+            .await
+            .map(Some)
+    }
+
     pub(crate) fn set_access_token(&self, access_token: Option<impl Into<String>>) {
         *self.access_token.borrow_mut() = access_token.map(|s| s.into());
     }
+
+    pub(crate) fn set_dpop(&self, dpop: Option<DpopKey>) {
+        *self.dpop.borrow_mut() = dpop;
+    }
 }
@@ -1,6 +1,16 @@
 //! Hooks for Yew
 
-use crate::{context::LatestAccessToken, prelude::OAuth2Context};
+use crate::{
+    agent::{AuthEvent, AuthEvents, duration_from_secs},
+    components::context::{AgentControl, NamedContexts},
+    context::{LatestAccessToken, Reason},
+    prelude::OAuth2Context,
+};
+use gloo_timers::callback::Interval;
+use gloo_utils::window;
+use js_sys::Date;
+use num_traits::ToPrimitive;
+use std::time::Duration;
 use yew::prelude::*;
 
 #[cfg(feature = "openid")]
@@ -11,6 +21,13 @@ pub mod openid {
     pub fn use_auth_agent() -> Option<crate::components::context::Agent<Client>> {
         crate::components::context::use_auth_agent::<Client>()
     }
+
+    #[yew::hook]
+    pub fn use_named_auth_agent(
+        namespace: &str,
+    ) -> Option<crate::components::context::Agent<Client>> {
+        crate::components::context::use_named_auth_agent::<Client>(namespace)
+    }
 }
 
 pub mod oauth2 {
@@ -20,6 +37,13 @@ pub mod oauth2 {
     pub fn use_auth_agent() -> Option<crate::components::context::Agent<Client>> {
         crate::components::context::use_auth_agent::<Client>()
     }
+
+    #[yew::hook]
+    pub fn use_named_auth_agent(
+        namespace: &str,
+    ) -> Option<crate::components::context::Agent<Client>> {
+        crate::components::context::use_named_auth_agent::<Client>(namespace)
+    }
 }
 
 /// Get the authentication state.
@@ -28,8 +52,198 @@ pub fn use_auth_state() -> Option<OAuth2Context> {
     use_context()
 }
 
+/// Get the authentication state of the context with the provided namespace.
+#[hook]
+pub fn use_named_auth_state(namespace: &str) -> Option<OAuth2Context> {
+    use_context::<NamedContexts>().and_then(|contexts| contexts.state(namespace))
+}
+
 /// Get a handle to retrieve the latest access token
 #[hook]
 pub fn use_latest_access_token() -> Option<LatestAccessToken> {
     use_context()
 }
+
+/// Receive the [`AuthEvent`]s of the agent.
+///
+/// The callback is invoked for every event, as long as the component is mounted. It is fine to
+/// pass a new callback on every render, the latest one will be used.
+#[hook]
+pub fn use_auth_events(callback: Callback<AuthEvent>) {
+    let events = use_context::<AuthEvents>();
+
+    let latest = use_mut_ref(|| callback.clone());
+    *latest.borrow_mut() = callback;
+
+    use_effect_with(events, move |events| {
+        let subscription = events.as_ref().map(|events| {
+            events.subscribe(Callback::from(move |event| {
+                let callback = latest.borrow().clone();
+                callback.emit(event);
+            }))
+        });
+        move || drop(subscription)
+    });
+}
+
+/// Check if the browser is offline.
+///
+/// While offline, the agent defers refreshing the token, and refreshes it once the browser is
+/// online again.
+#[hook]
+pub fn use_offline() -> bool {
+    let offline = use_state_eq(|| !window().navigator().on_line());
+
+    use_auth_events({
+        let offline = offline.setter();
+        Callback::from(move |event| match event {
+            AuthEvent::Offline => offline.set(true),
+            AuthEvent::Online => offline.set(false),
+            _ => {}
+        })
+    });
+
+    *offline
+}
+
+/// The time remaining until a session expires.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionExpiry {
+    /// The time until the access token expires.
+    pub access_token: Option<Duration>,
+    /// The time until the refresh token expires, if known.
+    pub refresh_token: Option<Duration>,
+    /// If the session has a refresh token.
+    pub refreshable: bool,
+    /// The time until the session ends, due to the idle timeout or session limit.
+    pub limit: Option<(Duration, Reason)>,
+}
+
+impl SessionExpiry {
+    /// The time until the session ends, unless it is being extended, and the reason.
+    ///
+    /// This is the earliest of the idle timeout or session limit, and the expiration of the
+    /// refresh token, or the access token if there is no refresh token.
+    pub fn remaining(&self) -> Option<(Duration, Reason)> {
+        let token = match self.refreshable {
+            true => self.refresh_token,
+            false => self.access_token,
+        };
+
+        token
+            .map(|remaining| (remaining, Reason::Expired))
+            .into_iter()
+            .chain(self.limit)
+            .min_by_key(|(remaining, _)| *remaining)
+    }
+}
+
+/// Get the time remaining until the session expires.
+///
+/// The component re-renders with the provided cadence, updating the remaining time. Returns
+/// [`None`] if the session is not authenticated.
+///
+/// See [`crate::components::SessionExpiryWarning`] for a ready-made component.
+#[hook]
+pub fn use_session_expiry(cadence: Duration) -> Option<SessionExpiry> {
+    let auth = use_context::<OAuth2Context>();
+    let control = use_context::<AgentControl>();
+
+    let update = use_force_update();
+    use_effect_with(cadence, move |cadence| {
+        let millis = cadence.as_millis().to_u32().unwrap_or(u32::MAX);
+        let interval = Interval::new(millis, move || update.force_update());
+        move || drop(interval)
+    });
+
+    let auth = auth?.authentication().cloned()?;
+    let now = Date::now() / 1000f64;
+    let remaining = |at: f64| duration_from_secs(at - now);
+
+    let deadlines = control.map(|control| control.deadlines);
+    let expires = deadlines
+        .as_ref()
+        .and_then(|deadlines| deadlines.expires())
+        .or(auth.expires.map(|expires| expires as f64));
+
+    Some(SessionExpiry {
+        access_token: expires.map(remaining),
+        refresh_token: auth
+            .refresh_expires
+            .map(|expires| remaining(expires as f64)),
+        refreshable: auth.refresh_token.is_some(),
+        limit: deadlines
+            .and_then(|deadlines| deadlines.session_end())
+            .map(|(at, reason)| (remaining(at), reason)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const fn secs(secs: u64) -> Option<Duration> {
+        Some(Duration::from_secs(secs))
+    }
+
+    fn expiry(
+        access_token: Option<Duration>,
+        refresh_token: Option<Duration>,
+        refreshable: bool,
+        limit: Option<(Duration, Reason)>,
+    ) -> SessionExpiry {
+        SessionExpiry {
+            access_token,
+            refresh_token,
+            refreshable,
+            limit,
+        }
+    }
+
+    #[test]
+    fn access_token_without_refresh_token() {
+        assert_eq!(
+            expiry(secs(60), None, false, None).remaining(),
+            Some((Duration::from_secs(60), Reason::Expired))
+        );
+    }
+
+    #[test]
+    fn refresh_token_of_refreshable_session() {
+        // the access token is refreshed, so only the refresh token ends the session
+        assert_eq!(
+            expiry(secs(60), secs(3600), true, None).remaining(),
+            Some((Duration::from_secs(3600), Reason::Expired))
+        );
+        // without a known expiration of the refresh token, the session doesn't end
+        assert_eq!(expiry(secs(60), None, true, None).remaining(), None);
+    }
+
+    #[test]
+    fn earliest_of_token_and_limit() {
+        let idle = Some((Duration::from_secs(300), Reason::IdleTimeout));
+        let limit = Some((Duration::from_secs(30), Reason::SessionLimit));
+
+        assert_eq!(
+            expiry(secs(60), None, false, idle).remaining(),
+            Some((Duration::from_secs(60), Reason::Expired))
+        );
+        assert_eq!(
+            expiry(secs(60), secs(3600), true, idle).remaining(),
+            Some((Duration::from_secs(300), Reason::IdleTimeout))
+        );
+        assert_eq!(
+            expiry(secs(60), secs(3600), true, limit).remaining(),
+            Some((Duration::from_secs(30), Reason::SessionLimit))
+        );
+        assert_eq!(
+            expiry(None, None, false, limit).remaining(),
+            Some((Duration::from_secs(30), Reason::SessionLimit))
+        );
+    }
+
+    #[test]
+    fn nothing_remaining() {
+        assert_eq!(expiry(None, None, false, None).remaining(), None);
+    }
+}
@@ -80,6 +80,13 @@ pub mod openid {
     pub fn use_auth_agent() -> Option<crate::components::context::Agent<Client>> {
         crate::components::context::use_auth_agent::<Client>()
     }
+
+    #[yew::hook]
+    pub fn use_named_auth_agent(
+        namespace: &str,
+    ) -> Option<crate::components::context::Agent<Client>> {
+        crate::components::context::use_named_auth_agent::<Client>(namespace)
+    }
 }
 
 pub mod oauth2 {
@@ -95,4 +102,11 @@ pub mod oauth2 {
     pub fn use_auth_agent() -> Option<crate::components::context::Agent<Client>> {
         crate::components::context::use_auth_agent::<Client>()
     }
+
+    #[yew::hook]
+    pub fn use_named_auth_agent(
+        namespace: &str,
+    ) -> Option<crate::components::context::Agent<Client>> {
+        crate::components::context::use_named_auth_agent::<Client>(namespace)
+    }
 }
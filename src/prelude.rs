@@ -1,6 +1,9 @@
 //! The prelude, includes most things you will need.
 
-pub use crate::agent::{LoginOptions, OAuth2Error, OAuth2Operations};
+pub use crate::agent::{
+    AuthEvent, AuthorizationDetail, LoginOptions, OAuth2Error, OAuth2Operations,
+    OAuth2OperationsExt, RefreshOptions,
+};
 pub use crate::components::*;
 pub use crate::context::*;
 pub use crate::hook::*;
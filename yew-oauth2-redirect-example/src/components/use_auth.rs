@@ -4,7 +4,7 @@ use yew_oauth2::prelude::*;
 
 #[derive(Clone, Debug, PartialEq, Properties)]
 pub struct Props {
-    #[prop_or(dummy())]
+    #[prop_or_default]
     pub auth: Authentication,
 }
 
@@ -14,17 +14,6 @@ impl UseAuthenticationProperties for Props {
     }
 }
 
-fn dummy() -> Authentication {
-    Authentication {
-        access_token: "".to_string(),
-        id_token: None,
-        refresh_token: None,
-        #[cfg(feature = "openid")]
-        claims: None,
-        expires: None,
-    }
-}
-
 #[function_component(ViewUseAuth)]
 pub fn view_use_auth(props: &Props) -> Html {
     html!(